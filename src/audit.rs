@@ -0,0 +1,61 @@
+//! Audit events for security-relevant actions.
+//!
+//! Events are emitted as `tracing` events with the [`AUDIT_TARGET`] target, at `INFO` level, so
+//! they can be routed separately from other logs. Every event has an `event` field naming the
+//! action, and events caused by a request also have `client_ip`, `user_agent` and `original_uri`
+//! fields where known.
+
+use axum::http::{header, HeaderMap};
+
+use crate::ratelimit::ClientIp;
+
+/// Target of all audit events.
+pub const AUDIT_TARGET: &str = "dumb_auth::audit";
+
+/// Details of the request that caused an audit event.
+pub(crate) struct AuditRequest<'a> {
+    pub client_ip: ClientIp,
+    pub user_agent: Option<&'a str>,
+    pub original_uri: Option<&'a str>,
+}
+
+impl<'a> AuditRequest<'a> {
+    pub fn new(client_ip: ClientIp, headers: &'a HeaderMap, original_uri: Option<&'a str>) -> Self {
+        Self {
+            client_ip,
+            user_agent: headers
+                .get(header::USER_AGENT)
+                .and_then(|value| value.to_str().ok()),
+            original_uri,
+        }
+    }
+}
+
+/// Emit an audit event, with the details of an [`AuditRequest`] if given.
+///
+/// ```ignore
+/// audit!("login_failed", &request, reason = "invalid_password");
+/// audit!("session_expired"; session_id = id.0);
+/// ```
+macro_rules! audit {
+    ($event:literal, $request:expr $(, $($fields:tt)+)?) => {{
+        let request: &$crate::audit::AuditRequest = $request;
+        ::tracing::info!(
+            target: $crate::audit::AUDIT_TARGET,
+            event = $event,
+            client_ip = request.client_ip.0.map(::tracing::field::display),
+            user_agent = request.user_agent,
+            original_uri = request.original_uri,
+            $($($fields)+)?
+        );
+    }};
+    ($event:literal $(; $($fields:tt)+)?) => {
+        ::tracing::info!(
+            target: $crate::audit::AUDIT_TARGET,
+            event = $event,
+            $($($fields)+)?
+        );
+    };
+}
+
+pub(crate) use audit;
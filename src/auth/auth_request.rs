@@ -3,19 +3,20 @@ use std::sync::Arc;
 use axum::{
     extract::State,
     http::{HeaderMap, StatusCode},
-    response::IntoResponse,
+    response::Response,
 };
 use tracing::error;
 
-use crate::{auth::Authenticator, AuthConfig};
+use crate::{auth::Authenticator, ratelimit::ClientIp, AuthConfig};
 
 const ORIGINAL_URI_HEADER: &str = "X-Original-URI";
 
 pub async fn handle_auth_request(
     State(auth_config): State<AuthConfig>,
     State(authenticator): State<Arc<Authenticator>>,
+    client_ip: ClientIp,
     headers: HeaderMap,
-) -> axum::response::Result<impl IntoResponse> {
+) -> axum::response::Result<Response> {
     let original_uri = headers
         .get(ORIGINAL_URI_HEADER)
         .ok_or_else(|| {
@@ -29,19 +30,9 @@ pub async fn handle_auth_request(
         })?;
 
     let result = authenticator
-        .authenticate(&auth_config, original_uri, &headers)
+        .authenticate(&auth_config, client_ip, original_uri, &headers)
         .await?;
 
-    let status = if result.valid {
-        StatusCode::OK
-    } else {
-        StatusCode::UNAUTHORIZED
-    };
-
-    Ok((
-        status,
-        result
-            .response_headers
-            .map_or_else(|| ().into_response(), IntoResponse::into_response),
-    ))
+    // nginx can't forward redirects from auth_request, so they're handled by error_page instead
+    Ok(result.into_proxy_response(false))
 }
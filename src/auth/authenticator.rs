@@ -1,9 +1,16 @@
 use std::sync::Arc;
 
-use axum::http::HeaderMap;
+use axum::http::{header, HeaderMap};
 use tracing::{debug, instrument};
 
-use crate::{passwords::PasswordChecker, sessions::SessionManager, AppError, AuthConfig};
+use crate::{
+    audit::{audit, AuditRequest},
+    metrics::Metrics,
+    passwords::PasswordChecker,
+    ratelimit::{ClientIp, RateLimiter},
+    sessions::SessionManager,
+    AppError, AuthConfig,
+};
 
 use super::{
     methods::{AuthMethod, BasicAuth, BearerAuth, SessionAuth},
@@ -14,6 +21,8 @@ pub(crate) struct Authenticator {
     basic: BasicAuth,
     bearer: BearerAuth,
     session: SessionAuth,
+    rate_limiter: Arc<RateLimiter>,
+    metrics: Arc<Metrics>,
 }
 
 impl Authenticator {
@@ -21,11 +30,15 @@ impl Authenticator {
         public_path: String,
         password_checker: Arc<PasswordChecker>,
         session_manager: Arc<SessionManager>,
+        rate_limiter: Arc<RateLimiter>,
+        metrics: Arc<Metrics>,
     ) -> Self {
         Self {
             basic: BasicAuth::new(password_checker.clone()),
             bearer: BearerAuth::new(password_checker),
             session: SessionAuth::new(public_path, session_manager),
+            rate_limiter,
+            metrics,
         }
     }
 
@@ -33,30 +46,67 @@ impl Authenticator {
     pub async fn authenticate(
         &self,
         auth_config: &AuthConfig,
+        client_ip: ClientIp,
         original_uri: &str,
         headers: &HeaderMap,
     ) -> Result<AuthResult, AppError> {
+        let audit_request = AuditRequest::new(client_ip, headers, Some(original_uri));
+
+        // Only limit attempts which actually provide a password. A client which is locked out can
+        // still use its session.
+        let has_password = self.has_password(auth_config, headers);
+        let locked_out = if has_password {
+            self.rate_limiter.check(client_ip).err()
+        } else {
+            None
+        };
+
         let result = self
-            .do_authenticate(auth_config, original_uri, headers)
+            .do_authenticate(auth_config, original_uri, headers, locked_out.is_none())
             .await?;
 
-        debug!("Auth: {}", if result.valid { "valid" } else { "invalid" });
+        if let Some(retry_after) = locked_out.filter(|_| !result.valid) {
+            self.metrics.auth_request("rate_limited");
+            audit!("auth_denied", &audit_request, reason = "rate_limited");
+            return Err(AppError::RateLimited(retry_after));
+        }
+
+        let outcome = if result.valid { "valid" } else { "invalid" };
+        debug!("Auth: {}", outcome);
+        self.metrics.auth_request(outcome);
+        if !result.valid {
+            audit!("auth_denied", &audit_request, reason = "invalid");
+        }
+
+        if has_password && locked_out.is_none() {
+            if result.valid {
+                self.rate_limiter.record_success(client_ip);
+            } else {
+                self.rate_limiter.record_failure(client_ip);
+            }
+        }
 
         Ok(result)
     }
 
+    /// Whether the request has a password which would be checked.
+    fn has_password(&self, auth_config: &AuthConfig, headers: &HeaderMap) -> bool {
+        (self.basic.is_allowed(auth_config) || self.bearer.is_allowed(auth_config))
+            && headers.contains_key(header::AUTHORIZATION)
+    }
+
     async fn do_authenticate(
         &self,
         auth_config: &AuthConfig,
         original_uri: &str,
         headers: &HeaderMap,
+        check_password: bool,
     ) -> Result<AuthResult, AppError> {
         let mut all_response_headers = None;
 
-        if self.basic.is_allowed(auth_config) {
+        if check_password && self.basic.is_allowed(auth_config) {
             match self
-                .basic
-                .verify(auth_config, original_uri, headers)
+                .verify(&self.basic, auth_config, original_uri, headers)
                 .await?
             {
                 result @ AuthResult { valid: true, .. } => return Ok(result),
@@ -64,10 +114,9 @@ impl Authenticator {
             }
         }
 
-        if self.bearer.is_allowed(auth_config) {
+        if check_password && self.bearer.is_allowed(auth_config) {
             match self
-                .bearer
-                .verify(auth_config, original_uri, headers)
+                .verify(&self.bearer, auth_config, original_uri, headers)
                 .await?
             {
                 result @ AuthResult { valid: true, .. } => return Ok(result),
@@ -77,8 +126,7 @@ impl Authenticator {
 
         if self.session.is_allowed(auth_config) {
             match self
-                .session
-                .verify(auth_config, original_uri, headers)
+                .verify(&self.session, auth_config, original_uri, headers)
                 .await?
             {
                 result @ AuthResult { valid: true, .. } => return Ok(result),
@@ -92,6 +140,18 @@ impl Authenticator {
         })
     }
 
+    async fn verify<M: AuthMethod>(
+        &self,
+        method: &M,
+        auth_config: &AuthConfig,
+        original_uri: &str,
+        headers: &HeaderMap,
+    ) -> Result<AuthResult, AppError> {
+        let result = method.verify(auth_config, original_uri, headers).await?;
+        self.metrics.auth_method(M::NAME, result.valid);
+        Ok(result)
+    }
+
     fn append_result(all_response_headers: &mut Option<HeaderMap>, auth_result: AuthResult) {
         if let Some(response_headers) = auth_result.response_headers {
             if let Some(all_response_headers) = all_response_headers {
@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, Uri},
+    response::Response,
+};
+
+use crate::{auth::Authenticator, ratelimit::ClientIp, AppConfig};
+
+/// Handler for Envoy's HTTP `ext_authz` filter.
+///
+/// Envoy sends the original request to `{ext_authz_prefix}{original_uri}`, and forwards our
+/// response to the client if it isn't `200`, so unauthenticated browsers are sent a `302` to the
+/// login page.
+///
+/// The auth config is taken from the same `AppConfig`, so a reload can't mix old and new config.
+pub async fn handle_ext_authz(
+    State(config): State<AppConfig>,
+    State(authenticator): State<Arc<Authenticator>>,
+    client_ip: ClientIp,
+    uri: Uri,
+    headers: HeaderMap,
+) -> axum::response::Result<Response> {
+    let original_uri = original_uri(&config.ext_authz_prefix, &uri);
+
+    let result = authenticator
+        .authenticate(&config.auth_config, client_ip, &original_uri, &headers)
+        .await?;
+
+    Ok(result.into_proxy_response(true))
+}
+
+fn original_uri(prefix: &str, uri: &Uri) -> String {
+    let path_and_query = uri.path_and_query().map_or("/", |pq| pq.as_str());
+    let original_uri = path_and_query
+        .strip_prefix(prefix)
+        .unwrap_or(path_and_query);
+
+    if original_uri.starts_with('/') {
+        original_uri.to_string()
+    } else {
+        format!("/{}", original_uri)
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::Response,
+};
+use tracing::{debug, error};
+
+use crate::{auth::Authenticator, ratelimit::ClientIp, AuthConfig};
+
+const FORWARDED_METHOD_HEADER: &str = "X-Forwarded-Method";
+const FORWARDED_PROTO_HEADER: &str = "X-Forwarded-Proto";
+const FORWARDED_HOST_HEADER: &str = "X-Forwarded-Host";
+const FORWARDED_URI_HEADER: &str = "X-Forwarded-Uri";
+
+/// Handler for Traefik's `ForwardAuth` middleware and Caddy's `forward_auth` directive.
+///
+/// Unlike `/auth_request`, the original URL is reconstructed from the `X-Forwarded-*` headers, and
+/// unauthenticated browsers are sent a `302` to the login page instead of a `401`.
+pub async fn handle_forward_auth(
+    State(auth_config): State<AuthConfig>,
+    State(authenticator): State<Arc<Authenticator>>,
+    client_ip: ClientIp,
+    headers: HeaderMap,
+) -> axum::response::Result<Response> {
+    let uri = get_header(&headers, FORWARDED_URI_HEADER)?.ok_or_else(|| {
+        error!("Request missing {} header", FORWARDED_URI_HEADER);
+        StatusCode::BAD_REQUEST
+    })?;
+    let proto = get_header(&headers, FORWARDED_PROTO_HEADER)?;
+    let host = get_header(&headers, FORWARDED_HOST_HEADER)?;
+
+    // Without the host we can only redirect back to a path on whichever host the login page is on
+    let original_uri = match (proto, host) {
+        (Some(proto), Some(host)) => format!("{}://{}{}", proto, host, uri),
+        _ => uri.to_string(),
+    };
+
+    debug!(
+        "Forwarded {} {}",
+        get_header(&headers, FORWARDED_METHOD_HEADER)?.unwrap_or("GET"),
+        original_uri
+    );
+
+    let result = authenticator
+        .authenticate(&auth_config, client_ip, &original_uri, &headers)
+        .await?;
+
+    Ok(result.into_proxy_response(true))
+}
+
+fn get_header<'a>(headers: &'a HeaderMap, name: &str) -> Result<Option<&'a str>, StatusCode> {
+    headers
+        .get(name)
+        .map(|value| {
+            value.to_str().map_err(|e| {
+                error!("Error decoding {} header: {}", name, e);
+                StatusCode::BAD_REQUEST
+            })
+        })
+        .transpose()
+}
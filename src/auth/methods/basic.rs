@@ -21,6 +21,8 @@ impl BasicAuth {
 }
 
 impl AuthMethod for BasicAuth {
+    const NAME: &'static str = "basic";
+
     fn is_allowed(&self, auth_config: &AuthConfig) -> bool {
         auth_config.allow_basic
     }
@@ -37,7 +39,12 @@ impl AuthMethod for BasicAuth {
                 .check_password(authorization.password(), &auth_config.password)
                 .await
             {
-                return Ok(AuthResult::valid());
+                return Ok(AuthResult::valid_with_identity(auth_config, Self::NAME)
+                    .with_identity_header(
+                        auth_config,
+                        |headers| &headers.user,
+                        authorization.username(),
+                    ));
             }
         }
 
@@ -21,6 +21,8 @@ impl BearerAuth {
 }
 
 impl AuthMethod for BearerAuth {
+    const NAME: &'static str = "bearer";
+
     fn is_allowed(&self, auth_config: &AuthConfig) -> bool {
         auth_config.allow_bearer
     }
@@ -37,7 +39,7 @@ impl AuthMethod for BearerAuth {
                 .check_password(authorization.token(), &auth_config.password)
                 .await
             {
-                Ok(AuthResult::valid())
+                Ok(AuthResult::valid_with_identity(auth_config, Self::NAME))
             } else {
                 Ok(AuthResult::invalid().with_header(
                     header::WWW_AUTHENTICATE,
@@ -11,6 +11,9 @@ mod bearer;
 mod session;
 
 pub trait AuthMethod {
+    /// Name of the method, used in the `IdentityHeaders::method` header and metrics.
+    const NAME: &'static str;
+
     fn is_allowed(&self, auth_config: &AuthConfig) -> bool;
 
     async fn verify(
@@ -7,6 +7,7 @@ use tracing::{error, warn};
 use crate::{
     auth::{methods::AuthMethod, AuthResult},
     config::AuthConfig,
+    login::create_session_cookie,
     sessions::SessionManager,
     AppError,
 };
@@ -26,6 +27,8 @@ impl SessionAuth {
 }
 
 impl AuthMethod for SessionAuth {
+    const NAME: &'static str = "session";
+
     fn is_allowed(&self, auth_config: &AuthConfig) -> bool {
         auth_config.allow_session
     }
@@ -38,8 +41,25 @@ impl AuthMethod for SessionAuth {
     ) -> Result<AuthResult, AppError> {
         if let Some(cookie) = headers.typed_get::<Cookie>() {
             if let Some(session_token) = cookie.get(&auth_config.session_cookie_name) {
-                if self.session_manager.check_session(session_token).await? {
-                    return Ok(AuthResult::valid());
+                if let Some(session) = self.session_manager.check_session(session_token).await? {
+                    let mut result = AuthResult::valid_with_identity(auth_config, Self::NAME)
+                        .with_identity_header(
+                            auth_config,
+                            |headers| &headers.session_id,
+                            session.id.to_string(),
+                        );
+
+                    // Re-issue the cookie so the browser doesn't expire it while it's in use
+                    if let Some(renewed) = session.renewed {
+                        let cookie =
+                            create_session_cookie(auth_config, renewed.token, renewed.max_age);
+                        match HeaderValue::try_from(cookie.to_string()) {
+                            Ok(value) => result = result.with_header(header::SET_COOKIE, value),
+                            Err(e) => error!("Error encoding session cookie: {}", e),
+                        }
+                    }
+
+                    return Ok(result);
                 }
             }
         }
@@ -1,9 +1,22 @@
-use axum::http::{header::IntoHeaderName, HeaderMap, HeaderValue};
+use axum::{
+    http::{
+        header::{self, IntoHeaderName},
+        HeaderMap, HeaderName, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
 
-pub(crate) use self::{auth_request::handle_auth_request, authenticator::Authenticator};
+use crate::{AuthConfig, IdentityHeaders};
+
+pub(crate) use self::{
+    auth_request::handle_auth_request, authenticator::Authenticator, ext_authz::handle_ext_authz,
+    forward_auth::handle_forward_auth,
+};
 
 mod auth_request;
 mod authenticator;
+mod ext_authz;
+mod forward_auth;
 mod methods;
 
 pub struct AuthResult {
@@ -26,10 +39,60 @@ impl AuthResult {
         }
     }
 
+    /// A valid result, with the method header if `AuthConfig::identity_headers` is set.
+    pub fn valid_with_identity(auth_config: &AuthConfig, method: &'static str) -> Self {
+        Self::valid().with_identity_header(auth_config, |headers| &headers.method, method)
+    }
+
+    /// Add the header chosen by `key` identifying the user, if `AuthConfig::identity_headers` is
+    /// set. Empty values or values that aren't valid header values are skipped.
+    pub fn with_identity_header(
+        self,
+        auth_config: &AuthConfig,
+        key: impl FnOnce(&IdentityHeaders) -> &HeaderName,
+        value: impl AsRef<str>,
+    ) -> Self {
+        let Some(headers) = &auth_config.identity_headers else {
+            return self;
+        };
+        if value.as_ref().is_empty() {
+            return self;
+        }
+
+        match HeaderValue::from_str(value.as_ref()) {
+            Ok(value) => self.with_header(key(headers).clone(), value),
+            Err(_) => self,
+        }
+    }
+
     pub fn with_header(mut self, key: impl IntoHeaderName, value: HeaderValue) -> Self {
         self.response_headers
             .get_or_insert_with(|| HeaderMap::with_capacity(1))
             .append(key, value);
         self
     }
+
+    /// Convert into a response for the reverse proxy.
+    ///
+    /// If `redirect` is set, invalid results with a `Location` header will respond with
+    /// `302 Found` so the proxy sends the browser to the login page, otherwise `401 Unauthorized`.
+    fn into_proxy_response(self, redirect: bool) -> Response {
+        let status = if self.valid {
+            StatusCode::OK
+        } else if redirect
+            && self
+                .response_headers
+                .as_ref()
+                .is_some_and(|headers| headers.contains_key(header::LOCATION))
+        {
+            StatusCode::FOUND
+        } else {
+            StatusCode::UNAUTHORIZED
+        };
+
+        match self.response_headers {
+            Some(headers) => (status, headers).into_response(),
+            None => status.into_response(),
+        }
+    }
 }
@@ -0,0 +1,197 @@
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use clap::{
+    error::ErrorKind, parser::ValueSource, Arg, ArgAction, ArgGroup, ArgMatches, Command,
+    CommandFactory, FromArgMatches,
+};
+use toml::{Table, Value};
+
+use super::Cli;
+
+const PASSWORD_GROUP: &str = "password_arg";
+
+impl Cli {
+    /// Parse args like [`clap::Parser::try_parse_from`], also loading options from the `--config`
+    /// file if given.
+    ///
+    /// Options in the config file are added as if they were given on the command line, except
+    /// where the option was already given on the command line or in the environment.
+    pub fn try_parse_with_config<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString>,
+    {
+        let args = args.into_iter().map(Into::into).collect::<Vec<OsString>>();
+
+        let mut command = Self::command();
+        let mut matches = command.try_get_matches_from_mut(&args)?;
+
+        // The config file only has run args, but could still be set in the environment for a
+        // subcommand, e.g. `healthcheck` in a container
+        let path = match matches.subcommand() {
+            None => matches.get_one::<PathBuf>("config").cloned(),
+            Some(_) => None,
+        };
+
+        if let Some(path) = path {
+            let config_args = config_args(&command, &matches, &path).map_err(|msg| {
+                command.error(
+                    ErrorKind::InvalidValue,
+                    format!("invalid config file '{}': {}", path.display(), msg),
+                )
+            })?;
+
+            matches = command.try_get_matches_from_mut(args.into_iter().chain(config_args))?;
+        }
+
+        let cli = Self::from_arg_matches(&matches).map_err(|e| e.format(&mut command))?;
+
+        if let (Some(args), None) = (&cli.args, &cli.cmd) {
+            if args.password.is_none()
+                && args.password_file.is_none()
+                && args.password_hash.is_none()
+                && args.password_hash_file.is_none()
+            {
+                return Err(command.error(
+                    ErrorKind::MissingRequiredArgument,
+                    "a password is required, set one of --password, --password-file, \
+                    --password-hash or --password-hash-file, or set one in the config file",
+                ));
+            }
+        }
+
+        Ok(cli)
+    }
+}
+
+/// Read the config file and convert it into command line args.
+fn config_args(
+    command: &Command,
+    matches: &ArgMatches,
+    path: &Path,
+) -> Result<Vec<OsString>, String> {
+    let table = fs::read_to_string(path)
+        .map_err(|e| e.to_string())?
+        .parse::<Table>()
+        .map_err(|e| e.to_string())?;
+
+    let mut args = Vec::new();
+
+    for (key, value) in flatten(table) {
+        let arg = find_arg(command, &key).ok_or_else(|| format!("unknown option '{}'", key))?;
+        let id = arg.get_id().as_str();
+
+        if let Some(other) = conflicting_arg(command, matches, id) {
+            return Err(format!(
+                "'{}' cannot be used with {} from the command line or environment",
+                key,
+                display_arg(command, other)
+            ));
+        }
+
+        // Command line and environment take precedence
+        if is_explicit(matches, id) {
+            continue;
+        }
+
+        let long = arg.get_long().expect("run args have long names");
+        let values = match value {
+            Value::Array(values) => values,
+            value => vec![value],
+        };
+
+        for value in values {
+            let value = match value {
+                Value::String(value) => value,
+                Value::Integer(value) => value.to_string(),
+                Value::Float(value) => value.to_string(),
+                Value::Boolean(value) => value.to_string(),
+                Value::Datetime(value) => value.to_string(),
+                Value::Array(_) | Value::Table(_) => {
+                    return Err(format!("unsupported value for '{}'", key))
+                }
+            };
+
+            if let ArgAction::SetTrue = arg.get_action() {
+                match value.parse::<bool>() {
+                    Ok(true) => args.push(format!("--{}", long).into()),
+                    Ok(false) => {}
+                    Err(_) => return Err(format!("'{}' must be true or false", key)),
+                }
+            } else {
+                args.push(format!("--{}={}", long, value).into());
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+/// Flatten tables into their parent, joining keys with `-`, so `[rate-limit]` with
+/// `attempts = 3` is the same as `rate-limit-attempts = 3`.
+fn flatten(table: Table) -> Vec<(String, Value)> {
+    let mut entries = Vec::new();
+
+    for (key, value) in table {
+        match value {
+            Value::Table(table) => entries.extend(
+                flatten(table)
+                    .into_iter()
+                    .map(|(child, value)| (format!("{}-{}", key, child), value)),
+            ),
+            value => entries.push((key, value)),
+        }
+    }
+
+    entries
+}
+
+/// Find the arg for a config key, which is either its long name or its field name in kebab-case.
+fn find_arg<'a>(command: &'a Command, key: &str) -> Option<&'a Arg> {
+    command
+        .get_arguments()
+        .filter(|arg| arg.get_id() != "config" && !arg.is_positional())
+        .find(|arg| arg.get_long() == Some(key) || arg.get_id().as_str().replace('_', "-") == key)
+}
+
+/// Find an arg given on the command line or in the environment that conflicts with `id`.
+///
+/// Args in the same (non-multiple) group conflict with each other. The password can't be set in
+/// both places at all, since it's unlikely to be intentional.
+fn conflicting_arg<'a>(command: &'a Command, matches: &ArgMatches, id: &str) -> Option<&'a str> {
+    command
+        .get_groups()
+        .filter(|group| {
+            !ArgGroup::clone(group).is_multiple() && group.get_args().any(|arg| arg == id)
+        })
+        .flat_map(|group| {
+            let include_self = group.get_id() == PASSWORD_GROUP;
+            group
+                .get_args()
+                .filter(move |arg| include_self || *arg != id)
+        })
+        .map(|arg| arg.as_str())
+        .find(|arg| is_explicit(matches, arg))
+}
+
+fn is_explicit(matches: &ArgMatches, id: &str) -> bool {
+    matches!(
+        matches.value_source(id),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    )
+}
+
+fn display_arg(command: &Command, id: &str) -> String {
+    match command
+        .get_arguments()
+        .find(|arg| arg.get_id() == id)
+        .and_then(Arg::get_long)
+    {
+        Some(long) => format!("--{}", long),
+        None => id.to_string(),
+    }
+}
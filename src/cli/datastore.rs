@@ -0,0 +1,165 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use clap::{Args, Subcommand};
+use dumb_auth::{
+    Datastore, DatastoreError, DatastoreOptions, MigrationReport, ReadMode, WriteMode,
+};
+
+use super::common::{die, fatal};
+
+/// Manage a datastore file.
+#[derive(Args, Debug, PartialEq)]
+pub struct DatastoreArgs {
+    #[command(subcommand)]
+    pub cmd: DatastoreCmd,
+}
+
+#[derive(Debug, PartialEq, Subcommand)]
+pub enum DatastoreCmd {
+    Migrate(MigrateArgs),
+}
+
+/// Migrate a datastore to the version used by this version of dumb-auth.
+///
+/// Datastores are migrated automatically when dumb-auth starts, this can be used to check what
+/// would happen first. Other commands, e.g. `dumb-auth sessions`, refuse to use a datastore which
+/// needs migrating. A backup of the datastore is saved next to it before migrating.
+///
+/// Stop any older version of dumb-auth using the datastore first, it won't be able to use the
+/// migrated datastore.
+#[derive(Args, Debug, PartialEq)]
+pub struct MigrateArgs {
+    #[command(flatten)]
+    pub datastore: DatastorePath,
+    /// Show the migrations that would be applied, without changing the datastore.
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Debug, PartialEq)]
+pub struct DatastorePath {
+    /// Datastore file, as given to `dumb-auth --datastore`.
+    #[arg(long, env = "DUMB_AUTH_DATASTORE", hide_env = true)]
+    pub datastore: DatastoreLocation,
+}
+
+impl DatastorePath {
+    /// Open the existing datastore, without the write thread since it's only used briefly.
+    ///
+    /// Older datastores aren't migrated, that's left to `dumb-auth datastore migrate` or starting
+    /// the server.
+    pub fn open(&self) -> Datastore {
+        self.check_exists();
+
+        let result = self.datastore.open(DatastoreOptions {
+            read_mode: ReadMode::Sync,
+            write_mode: WriteMode::Sync,
+            migrate: false,
+            ..Default::default()
+        });
+
+        match result {
+            Ok(datastore) => datastore,
+            Err(DatastoreError::NeedsMigration { from, to }) => die(&format!(
+                "Datastore '{}' is version {} and needs migrating to version {}, run \
+                `dumb-auth datastore migrate` first",
+                self.datastore, from, to
+            )),
+            Err(e) => fatal("opening datastore", e),
+        }
+    }
+
+    /// Opening would otherwise create an empty datastore.
+    fn check_exists(&self) {
+        if !self.datastore.path().is_file() {
+            die(&format!("Datastore '{}' does not exist", self.datastore));
+        }
+    }
+}
+
+/// A datastore file, either `sqlite:<path>` for SQLite, or `[lmdb:]<path>` for LMDB.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DatastoreLocation {
+    Lmdb(PathBuf),
+    Sqlite(PathBuf),
+}
+
+impl DatastoreLocation {
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Lmdb(path) | Self::Sqlite(path) => path,
+        }
+    }
+
+    pub fn open(&self, options: DatastoreOptions) -> Result<Datastore, DatastoreError> {
+        match self {
+            Self::Lmdb(path) => Datastore::open_with(path, options),
+            Self::Sqlite(path) => Datastore::open_sqlite_with(path, options),
+        }
+    }
+
+    pub fn migrate(&self, dry_run: bool) -> Result<MigrationReport, DatastoreError> {
+        match self {
+            Self::Lmdb(path) => Datastore::migrate(path, dry_run),
+            Self::Sqlite(path) => Datastore::migrate_sqlite(path, dry_run),
+        }
+    }
+}
+
+impl From<&str> for DatastoreLocation {
+    fn from(s: &str) -> Self {
+        if let Some(path) = s.strip_prefix("sqlite:") {
+            Self::Sqlite(path.into())
+        } else {
+            Self::Lmdb(s.strip_prefix("lmdb:").unwrap_or(s).into())
+        }
+    }
+}
+
+impl fmt::Display for DatastoreLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.path().display().fmt(f)
+    }
+}
+
+pub fn datastore(args: DatastoreArgs) {
+    match args.cmd {
+        DatastoreCmd::Migrate(args) => migrate(args),
+    }
+}
+
+fn migrate(args: MigrateArgs) {
+    args.datastore.check_exists();
+
+    let report = args
+        .datastore
+        .datastore
+        .migrate(args.dry_run)
+        .unwrap_or_else(|e| fatal("migrating datastore", e));
+
+    if report.migrations.is_empty() {
+        println!("Datastore is already at version {}", report.to_version);
+        return;
+    }
+
+    println!(
+        "{} datastore from version {} to {}:",
+        if args.dry_run {
+            "Would migrate"
+        } else {
+            "Migrated"
+        },
+        report.from_version,
+        report.to_version
+    );
+    for (version, description) in (report.from_version..).zip(&report.migrations) {
+        println!("  {} -> {}: {}", version, version + 1, description);
+    }
+
+    if let Some(backup) = report.backup {
+        println!("Backup saved to {}", backup.display());
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    str::FromStr,
+    time::Duration,
+};
+
+use clap::Args;
+
+use super::{common::die, run::parse_std_duration};
+
+/// Check that dumb-auth is running, for use as a container health check.
+///
+/// Exits with a non-zero status if the request fails or doesn't return a 2xx status. `/health`
+/// only checks the server is responding, `/ready` also checks the datastore can be used.
+#[derive(Args, Debug, PartialEq)]
+pub struct HealthcheckArgs {
+    /// URL to check, only plain `http://` URLs are supported.
+    #[arg(
+        long,
+        env = "DUMB_AUTH_HEALTHCHECK_URL",
+        hide_env = true,
+        default_value = "http://127.0.0.1:3862/health"
+    )]
+    pub url: HealthcheckUrl,
+    /// How long to wait for a response.
+    #[arg(long, default_value = "5s", value_parser = parse_std_duration)]
+    pub timeout: Duration,
+}
+
+/// The parts of a `http://host[:port][/path]` URL needed to make a request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HealthcheckUrl {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl FromStr for HealthcheckUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = s
+            .strip_prefix("http://")
+            .ok_or("URL must start with http://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, "/"),
+        };
+
+        // Split off the port, taking care not to split an IPv6 address
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (
+                host,
+                port.parse()
+                    .map_err(|_| format!("invalid port '{}'", port))?,
+            ),
+            _ => (authority, 80),
+        };
+
+        if host.is_empty() {
+            return Err("URL must have a host".into());
+        }
+
+        Ok(Self {
+            host: host.into(),
+            port,
+            path: path.into(),
+        })
+    }
+}
+
+pub fn healthcheck(args: HealthcheckArgs) {
+    if let Err(e) = check(&args.url, args.timeout) {
+        die(&format!("Health check failed: {e}"));
+    }
+}
+
+/// Send a `GET` request to the URL and check the response has a 2xx status.
+pub(super) fn check(url: &HealthcheckUrl, timeout: Duration) -> Result<(), String> {
+    let host = url.host.trim_start_matches('[').trim_end_matches(']');
+    let addr = (host, url.port)
+        .to_socket_addrs()
+        .map_err(|e| format!("resolving {}: {e}", url.host))?
+        .next()
+        .ok_or_else(|| format!("no addresses for {}", url.host))?;
+
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)
+        .map_err(|e| format!("connecting to {addr}: {e}"))?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .and_then(|_| stream.set_write_timeout(Some(timeout)))
+        .map_err(|e| e.to_string())?;
+
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: close\r\n\r\n",
+        url.path, url.host, url.port
+    )
+    .map_err(|e| format!("sending request: {e}"))?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut status_line)
+        .map_err(|e| format!("reading response: {e}"))?;
+
+    let status_line = status_line.trim_end();
+    match status_line.split(' ').nth(1) {
+        Some(status) if status.starts_with('2') && status.len() == 3 => Ok(()),
+        Some(_) => Err(status_line.into()),
+        None => Err(format!("invalid response '{}'", status_line)),
+    }
+}
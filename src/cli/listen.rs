@@ -0,0 +1,276 @@
+use std::{
+    fmt,
+    future::Future,
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use axum::Router;
+use tokio::net::TcpListener;
+
+/// An address to listen for requests on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BindAddr {
+    Tcp(SocketAddr),
+    /// Path of a Unix domain socket, given as `unix:<path>`.
+    Unix(PathBuf),
+}
+
+impl FromStr for BindAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if !cfg!(unix) {
+                Err("unix sockets are not supported on this platform".into())
+            } else if path.is_empty() {
+                Err("unix socket path must not be empty".into())
+            } else {
+                Ok(Self::Unix(path.into()))
+            }
+        } else {
+            s.parse()
+                .map(Self::Tcp)
+                .map_err(|_| "expected an IP address and port, or unix:<path>".into())
+        }
+    }
+}
+
+impl fmt::Display for BindAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "http://{}", addr),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Owner to set on Unix domain sockets, given as `user`, `user:group` or `:group`.
+///
+/// Users and groups can be names or numeric IDs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SocketOwner {
+    user: Option<String>,
+    group: Option<String>,
+}
+
+impl FromStr for SocketOwner {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, group) = match s.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (s, None),
+        };
+
+        let owner = Self {
+            user: Some(user).filter(|user| !user.is_empty()).map(Into::into),
+            group: group.filter(|group| !group.is_empty()).map(Into::into),
+        };
+
+        if owner.user.is_none() && owner.group.is_none() {
+            Err("expected user, user:group or :group".into())
+        } else {
+            Ok(owner)
+        }
+    }
+}
+
+/// Options for Unix domain sockets.
+#[derive(Clone, Debug, Default)]
+pub struct SocketOptions {
+    pub mode: Option<u32>,
+    pub owner: Option<SocketOwner>,
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    /// A Unix domain socket, and the path to remove when done if we created it.
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, Option<PathBuf>),
+}
+
+impl Listener {
+    pub async fn bind(addr: &BindAddr, options: &SocketOptions) -> io::Result<Self> {
+        match addr {
+            BindAddr::Tcp(addr) => Ok(Self::Tcp(TcpListener::bind(addr).await?)),
+            #[cfg(unix)]
+            BindAddr::Unix(path) => Ok(Self::Unix(bind_unix(path, options)?, Some(path.clone()))),
+            #[cfg(not(unix))]
+            BindAddr::Unix(_) => {
+                let _ = options;
+                Err(io::ErrorKind::Unsupported.into())
+            }
+        }
+    }
+
+    /// Use an already bound listener, e.g. from systemd.
+    pub fn from_std_tcp(listener: std::net::TcpListener) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        Ok(Self::Tcp(TcpListener::from_std(listener)?))
+    }
+
+    /// Use an already bound listener, e.g. from systemd. The socket file won't be removed.
+    #[cfg(unix)]
+    pub fn from_std_unix(listener: std::os::unix::net::UnixListener) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        Ok(Self::Unix(
+            tokio::net::UnixListener::from_std(listener)?,
+            None,
+        ))
+    }
+
+    pub fn is_unix(&self) -> bool {
+        match self {
+            Self::Tcp(_) => false,
+            #[cfg(unix)]
+            Self::Unix(..) => true,
+        }
+    }
+
+    /// Serve the app until `shutdown` completes and all connections have closed.
+    pub async fn serve(
+        self,
+        app: Router,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> io::Result<()> {
+        match self {
+            Self::Tcp(listener) => {
+                axum::serve(
+                    listener,
+                    app.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown(shutdown)
+                .await
+            }
+            #[cfg(unix)]
+            Self::Unix(listener, path) => {
+                let result = axum::serve(listener, app.into_make_service())
+                    .with_graceful_shutdown(shutdown)
+                    .await;
+                if let Some(path) = path {
+                    remove_socket(&path)?;
+                }
+                result
+            }
+        }
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "http://{}", addr),
+                Err(_) => write!(f, "unknown TCP address"),
+            },
+            // The listener's own address is where it was bound, before being moved into place
+            #[cfg(unix)]
+            Self::Unix(_, Some(path)) => write!(f, "unix:{}", path.display()),
+            #[cfg(unix)]
+            Self::Unix(listener, None) => match listener.local_addr() {
+                Ok(addr) => match addr.as_pathname() {
+                    Some(path) => write!(f, "unix:{}", path.display()),
+                    None => write!(f, "unnamed unix socket"),
+                },
+                Err(_) => write!(f, "unknown unix socket"),
+            },
+        }
+    }
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &Path, options: &SocketOptions) -> io::Result<tokio::net::UnixListener> {
+    use std::{ffi::OsString, fs, os::unix::fs::PermissionsExt};
+
+    // Left over from a previous run that didn't shut down cleanly
+    remove_socket(path)?;
+
+    // Bind in a private directory and move the socket into place once its owner and mode are set,
+    // so it's never reachable with the wrong permissions
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut template = OsString::from(".");
+    template.push(path.file_name().unwrap_or("dumb-auth.sock".as_ref()));
+    template.push(".XXXXXX");
+    let private_dir = nix::unistd::mkdtemp(&parent.join(template))?;
+    let private_path = private_dir.join("socket");
+
+    let result = (|| {
+        let listener = tokio::net::UnixListener::bind(&private_path)?;
+
+        if let Some(owner) = &options.owner {
+            let (uid, gid) = owner.resolve()?;
+            std::os::unix::fs::chown(&private_path, uid, gid)?;
+        }
+
+        if let Some(mode) = options.mode {
+            fs::set_permissions(&private_path, fs::Permissions::from_mode(mode))?;
+        }
+
+        fs::rename(&private_path, path)?;
+        Ok(listener)
+    })();
+
+    let _ = fs::remove_file(&private_path);
+    fs::remove_dir(&private_dir)?;
+    result
+}
+
+/// Remove the socket file if it exists, but refuse to remove anything that isn't a socket.
+#[cfg(unix)]
+fn remove_socket(path: &Path) -> io::Result<()> {
+    use std::{fs, os::unix::fs::FileTypeExt};
+
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists and is not a socket", path.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(unix)]
+impl SocketOwner {
+    fn resolve(&self) -> io::Result<(Option<u32>, Option<u32>)> {
+        use nix::unistd::{Group, User};
+
+        let not_found = |kind, name| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} '{}' not found", kind, name),
+            )
+        };
+
+        let uid = match &self.user {
+            Some(user) => Some(match user.parse() {
+                Ok(uid) => uid,
+                Err(_) => User::from_name(user)?
+                    .ok_or_else(|| not_found("user", user))?
+                    .uid
+                    .as_raw(),
+            }),
+            None => None,
+        };
+
+        let gid = match &self.group {
+            Some(group) => Some(match group.parse() {
+                Ok(gid) => gid,
+                Err(_) => Group::from_name(group)?
+                    .ok_or_else(|| not_found("group", group))?
+                    .gid
+                    .as_raw(),
+            }),
+            None => None,
+        };
+
+        Ok((uid, gid))
+    }
+}
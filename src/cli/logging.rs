@@ -0,0 +1,101 @@
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    path::Path,
+    sync::Mutex,
+};
+
+use clap::ValueEnum;
+use dumb_auth::AUDIT_TARGET;
+use tracing::{level_filters::LevelFilter, Level};
+use tracing_subscriber::{
+    filter::{filter_fn, FilterExt, Targets},
+    fmt,
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    EnvFilter, Layer,
+};
+
+use super::{common::fatal, Cli, Cmd};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+/// Install the global logger for the parsed args.
+///
+/// Audit events go to the `--audit-log` file instead of the main log, if given for `run` or
+/// `sessions`.
+pub fn init_logging(cli: &Cli) {
+    let format = cli
+        .args
+        .as_ref()
+        .map(|args| args.log_format)
+        .unwrap_or_default();
+    let audit_log = match (&cli.cmd, &cli.args) {
+        (None, Some(args)) => args.audit_log.as_deref(),
+        (Some(Cmd::Sessions(args)), _) => args.audit_log.as_deref(),
+        _ => None,
+    };
+
+    let env_filter = EnvFilter::builder()
+        .with_env_var("DUMB_AUTH_LOG")
+        .with_default_directive(LevelFilter::INFO.into())
+        .from_env_lossy();
+    let has_audit_log = audit_log.is_some();
+    let main_filter = env_filter.and(filter_fn(move |meta| {
+        !has_audit_log || meta.target() != AUDIT_TARGET
+    }));
+
+    let main_layer = match format {
+        LogFormat::Text => fmt::layer().boxed(),
+        LogFormat::Json => fmt::layer().json().boxed(),
+    }
+    .with_filter(main_filter);
+
+    let (audit_layer, audit_error) = match audit_log.map(open_audit_log) {
+        Some(Ok(file)) => (Some(audit_layer(file)), None),
+        Some(Err(e)) => (None, Some(e)),
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(main_layer)
+        .with(audit_layer)
+        .init();
+
+    if let Some(e) = audit_error {
+        fatal("opening audit log", e);
+    }
+}
+
+fn open_audit_log(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o640);
+
+    options.open(path)
+}
+
+fn audit_layer<S>(file: File) -> impl Layer<S>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    fmt::layer()
+        .json()
+        .flatten_event(true)
+        .with_current_span(false)
+        .with_span_list(false)
+        .with_level(false)
+        .with_target(false)
+        .with_ansi(false)
+        .with_writer(Mutex::new(file))
+        .with_filter(Targets::new().with_target(AUDIT_TARGET, Level::INFO))
+}
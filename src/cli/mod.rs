@@ -1,11 +1,25 @@
 use clap::{Parser, Subcommand};
 
-pub use self::{passwd::passwd, run::run};
-use self::{passwd::PasswdArgs, run::RunArgs};
+pub use self::{
+    datastore::datastore, healthcheck::healthcheck, logging::init_logging, passwd::passwd,
+    run::run, sessions::sessions, totp::totp,
+};
+use self::{
+    datastore::DatastoreArgs, healthcheck::HealthcheckArgs, passwd::PasswdArgs, run::RunArgs,
+    sessions::SessionsArgs, totp::TotpArgs,
+};
 
 mod common;
+mod config;
+pub mod datastore;
+pub mod healthcheck;
+mod listen;
+mod logging;
 pub mod passwd;
 pub mod run;
+pub mod sessions;
+mod systemd;
+pub mod totp;
 
 #[derive(Debug, PartialEq, Parser)]
 #[command(about, author, version, args_conflicts_with_subcommands = true)]
@@ -20,6 +34,10 @@ pub struct Cli {
 #[derive(Debug, PartialEq, Subcommand)]
 pub enum Cmd {
     Passwd(PasswdArgs),
+    Totp(TotpArgs),
+    Healthcheck(HealthcheckArgs),
+    Sessions(SessionsArgs),
+    Datastore(DatastoreArgs),
 }
 
 #[cfg(test)]
@@ -27,13 +45,14 @@ mod tests {
     use std::{env, iter, path::PathBuf};
 
     use clap::CommandFactory;
+    use dumb_auth::IdentityHeaders;
 
     use super::*;
 
     const PWARG: &str = "--password=hunter2";
 
     fn sut(args: &[&str]) -> Result<Cli, String> {
-        Cli::try_parse_from(iter::once(&"dumb-auth").chain(args)).map_err(|e| e.to_string())
+        Cli::try_parse_with_config(iter::once(&"dumb-auth").chain(args)).map_err(|e| e.to_string())
     }
 
     #[test]
@@ -101,42 +120,675 @@ mod tests {
     #[test]
     fn test_allow_session() {
         // Defaults to true
-        assert_eq!(sut(&[PWARG]).unwrap().args.unwrap().allow_session, true);
-        assert_eq!(
+        assert!(sut(&[PWARG]).unwrap().args.unwrap().allow_session);
+        assert!(
             sut(&[PWARG, "--allow-session"])
                 .unwrap()
                 .args
                 .unwrap()
-                .allow_session,
-            true
+                .allow_session
         );
 
         // Parses explicit value
-        assert_eq!(
+        assert!(
             sut(&[PWARG, "--allow-session=true"])
                 .unwrap()
                 .args
                 .unwrap()
-                .allow_session,
-            true
+                .allow_session
         );
-        assert_eq!(
-            sut(&[PWARG, "--allow-session=false"])
+        assert!(
+            !sut(&[PWARG, "--allow-session=false"])
                 .unwrap()
                 .args
                 .unwrap()
-                .allow_session,
-            false
+                .allow_session
         );
 
         // Parses value from env
         env::set_var("DUMB_AUTH_ALLOW_SESSION", "false");
-        assert_eq!(sut(&[PWARG]).unwrap().args.unwrap().allow_session, false);
+        assert!(!sut(&[PWARG]).unwrap().args.unwrap().allow_session);
         env::set_var("DUMB_AUTH_ALLOW_SESSION", "true");
-        assert_eq!(sut(&[PWARG]).unwrap().args.unwrap().allow_session, true);
+        assert!(sut(&[PWARG]).unwrap().args.unwrap().allow_session);
         env::remove_var("DUMB_AUTH_ALLOW_SESSION");
     }
 
+    #[test]
+    fn test_identity_headers() {
+        // Disabled by default
+        let auth_config = sut(&[PWARG]).unwrap().args.unwrap().auth_config().unwrap();
+        assert_eq!(auth_config.identity_headers, None);
+
+        // Uses the default names when enabled
+        let auth_config = sut(&[PWARG, "--identity-headers"])
+            .unwrap()
+            .args
+            .unwrap()
+            .auth_config()
+            .unwrap();
+        assert_eq!(
+            auth_config.identity_headers,
+            Some(IdentityHeaders::default())
+        );
+
+        // Parses explicit names
+        let auth_config = sut(&[
+            PWARG,
+            "--identity-headers",
+            "--auth-method-header=Remote-Auth-Method",
+            "--auth-session-id-header=Remote-Session",
+            "--auth-user-header=Remote-User",
+        ])
+        .unwrap()
+        .args
+        .unwrap()
+        .auth_config()
+        .unwrap();
+        let headers = auth_config.identity_headers.unwrap();
+        assert_eq!(headers.method, "remote-auth-method");
+        assert_eq!(headers.session_id, "remote-session");
+        assert_eq!(headers.user, "remote-user");
+
+        // Rejects invalid names
+        assert!(sut(&[PWARG, "--auth-user-header=Remote User"]).is_err());
+    }
+
+    #[test]
+    fn test_session_sweep_interval() {
+        // Defaults to 1 hour
+        assert_eq!(
+            sut(&[PWARG]).unwrap().args.unwrap().session_sweep_interval,
+            time::Duration::hours(1)
+        );
+
+        // Parses durations
+        assert_eq!(
+            sut(&[PWARG, "--session-sweep-interval=10m"])
+                .unwrap()
+                .args
+                .unwrap()
+                .session_sweep_interval,
+            time::Duration::minutes(10)
+        );
+        assert!(sut(&[PWARG, "--session-sweep-interval=0s"])
+            .unwrap()
+            .args
+            .unwrap()
+            .session_sweep_interval
+            .is_zero());
+
+        // Rejects invalid durations
+        assert!(sut(&[PWARG, "--session-sweep-interval=often"]).is_err());
+    }
+
+    #[test]
+    fn test_session_idle_timeout() {
+        // Disabled by default
+        assert_eq!(
+            sut(&[PWARG]).unwrap().args.unwrap().session_idle_timeout,
+            None
+        );
+
+        assert_eq!(
+            sut(&[PWARG, "--session-idle-timeout=30m"])
+                .unwrap()
+                .args
+                .unwrap()
+                .session_idle_timeout,
+            Some(time::Duration::minutes(30))
+        );
+
+        // Rejects zero and invalid durations
+        assert!(sut(&[PWARG, "--session-idle-timeout=0s"]).is_err());
+        assert!(sut(&[PWARG, "--session-idle-timeout=soon"]).is_err());
+    }
+
+    #[test]
+    fn test_session_key_file() {
+        let args = sut(&[
+            PWARG,
+            "--session-key-file=session-keys",
+            "--revoked-sessions-file=revoked-sessions",
+        ])
+        .unwrap()
+        .args
+        .unwrap();
+        assert_eq!(args.session_key_file, Some(PathBuf::from("session-keys")));
+        assert_eq!(
+            args.revoked_sessions_file,
+            Some(PathBuf::from("revoked-sessions"))
+        );
+
+        // Revoked sessions are only for signed sessions
+        assert!(sut(&[PWARG, "--revoked-sessions-file=revoked-sessions"])
+            .unwrap_err()
+            .contains("--session-key-file"));
+    }
+
+    #[test]
+    fn test_datastore() {
+        use super::datastore::DatastoreLocation;
+
+        let datastore = |arg: &str| {
+            sut(&[PWARG, &format!("--datastore={}", arg)])
+                .unwrap()
+                .args
+                .unwrap()
+                .datastore
+        };
+
+        // Defaults to in memory
+        assert_eq!(sut(&[PWARG]).unwrap().args.unwrap().datastore, None);
+
+        // LMDB unless prefixed with "sqlite:"
+        assert_eq!(
+            datastore("/var/lib/dumb-auth/datastore"),
+            Some(DatastoreLocation::Lmdb(
+                "/var/lib/dumb-auth/datastore".into()
+            ))
+        );
+        assert_eq!(
+            datastore("lmdb:datastore"),
+            Some(DatastoreLocation::Lmdb("datastore".into()))
+        );
+        assert_eq!(
+            datastore("sqlite:/var/lib/dumb-auth/sessions.db"),
+            Some(DatastoreLocation::Sqlite(
+                "/var/lib/dumb-auth/sessions.db".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_datastore_max_size() {
+        let max_size = |arg: &str| {
+            sut(&[PWARG, &format!("--datastore-max-size={}", arg)])
+                .map(|cli| cli.args.unwrap().datastore_max_size)
+        };
+
+        // Defaults to 1GiB
+        assert_eq!(
+            sut(&[PWARG]).unwrap().args.unwrap().datastore_max_size,
+            1024 * 1024 * 1024
+        );
+
+        // Parses sizes with units
+        assert_eq!(max_size("2097152"), Ok(2 * 1024 * 1024));
+        assert_eq!(max_size("64M"), Ok(64 * 1024 * 1024));
+        assert_eq!(max_size("64MiB"), Ok(64 * 1024 * 1024));
+        assert_eq!(max_size("2gib"), Ok(2 * 1024 * 1024 * 1024));
+
+        // Rejects invalid sizes
+        assert!(max_size("1KiB").unwrap_err().contains("at least 1MiB"));
+        assert!(max_size("64MB").unwrap_err().contains("unknown size unit"));
+        assert!(max_size("MiB").is_err());
+    }
+
+    #[test]
+    fn test_totp_secret() {
+        // Accepts a single TOTP secret arg
+        assert_eq!(
+            sut(&[PWARG, "--totp-secret=JBSWY3DPEHPK3PXP"])
+                .unwrap()
+                .args
+                .unwrap()
+                .totp_secret
+                .as_deref(),
+            Some("JBSWY3DPEHPK3PXP")
+        );
+        assert_eq!(
+            sut(&[PWARG, "--totp-secret-file=totp.txt"])
+                .unwrap()
+                .args
+                .unwrap()
+                .totp_secret_file,
+            Some(PathBuf::from("totp.txt"))
+        );
+
+        // Disallows multiple TOTP secret args
+        assert!(sut(&[
+            PWARG,
+            "--totp-secret=JBSWY3DPEHPK3PXP",
+            "--totp-secret-file=totp.txt"
+        ])
+        .unwrap_err()
+        .contains("cannot be used with '--totp-secret"));
+    }
+
+    #[test]
+    fn test_totp() {
+        // Does not require run args
+        assert_eq!(
+            sut(&["totp"]).unwrap().cmd.unwrap(),
+            Cmd::Totp(TotpArgs {
+                output: None,
+                issuer: "dumb-auth".into(),
+                account: "dumb-auth".into(),
+            })
+        );
+
+        // Accepts outfile and labels
+        assert_eq!(
+            sut(&["totp", "--issuer=Example", "--account=me", "outfile"])
+                .unwrap()
+                .cmd
+                .unwrap(),
+            Cmd::Totp(TotpArgs {
+                output: Some("outfile".into()),
+                issuer: "Example".into(),
+                account: "me".into(),
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_totp_output_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("totp.txt");
+        totp(TotpArgs {
+            output: Some(path.clone()),
+            issuer: "dumb-auth".into(),
+            account: "dumb-auth".into(),
+        });
+
+        // Only readable by the owner
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        let secret = std::fs::read_to_string(&path).unwrap();
+        assert!(secret.trim().parse::<dumb_auth::TotpSecret>().is_ok());
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let args = sut(&[PWARG]).unwrap().args.unwrap();
+
+        // Disabled by default, since behind a proxy every client shares the proxy's address
+        assert!(!args.rate_limit);
+        assert_eq!(args.rate_limit_attempts, 5);
+        assert_eq!(args.rate_limit_lockout, std::time::Duration::from_secs(60));
+        assert_eq!(args.client_ip_header, None);
+
+        // Parses explicit values
+        let args = sut(&[
+            PWARG,
+            "--rate-limit",
+            "--rate-limit-attempts=3",
+            "--client-ip-header=X-Real-IP",
+        ])
+        .unwrap()
+        .args
+        .unwrap();
+        assert!(args.rate_limit);
+        assert_eq!(args.rate_limit_attempts, 3);
+        assert_eq!(args.client_ip_header.unwrap(), "x-real-ip");
+
+        // Requires at least one attempt
+        assert!(sut(&[PWARG, "--rate-limit-attempts=0"]).is_err());
+    }
+
+    #[test]
+    fn test_ext_authz_prefix() {
+        assert_eq!(
+            sut(&[PWARG]).unwrap().args.unwrap().ext_authz_prefix,
+            "/ext_authz"
+        );
+        assert_eq!(
+            sut(&[PWARG, "--ext-authz-prefix=/envoy/authz"])
+                .unwrap()
+                .args
+                .unwrap()
+                .ext_authz_prefix,
+            "/envoy/authz"
+        );
+
+        // Would conflict with every other route
+        assert!(sut(&[PWARG, "--ext-authz-prefix=/"]).is_err());
+        assert!(sut(&[PWARG, "--ext-authz-prefix=/envoy/"]).is_err());
+    }
+
+    #[test]
+    fn test_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = |contents: &str| {
+            let path = dir.path().join("config.toml");
+            std::fs::write(&path, contents).unwrap();
+            format!("--config={}", path.display())
+        };
+
+        // Loads options from the file
+        let args = sut(&[&config(
+            r#"
+            password-file = "password.txt"
+            allow-basic = true
+            rate-limit = true
+            rate-limit-attempts = 3
+            session-expiry = "7d"
+            allowed-redirect-hosts = ["a.example.com", "b.example.com"]
+            "#,
+        )])
+        .unwrap()
+        .args
+        .unwrap();
+        assert_eq!(args.password_file, Some(PathBuf::from("password.txt")));
+        assert!(args.allow_basic);
+        assert!(args.rate_limit);
+        assert_eq!(args.rate_limit_attempts, 3);
+        assert_eq!(args.session_expiry.to_string(), "1w");
+        assert_eq!(
+            args.allowed_redirect_hosts,
+            vec!["a.example.com", "b.example.com"]
+        );
+
+        // Command line takes precedence
+        let args = sut(&[
+            &config("password = \"hunter2\"\nrate-limit-attempts = 3"),
+            "--rate-limit-attempts=7",
+        ])
+        .unwrap()
+        .args
+        .unwrap();
+        assert_eq!(args.password.as_deref(), Some("hunter2"));
+        assert_eq!(args.rate_limit_attempts, 7);
+
+        // Doesn't allow setting the password in both
+        assert!(sut(&[&config("password = \"hunter2\""), PWARG])
+            .unwrap_err()
+            .contains("'password' cannot be used with --password"));
+        assert!(sut(&[&config("password-file = \"password.txt\""), PWARG])
+            .unwrap_err()
+            .contains("'password-file' cannot be used with --password"));
+
+        // Still requires a password
+        assert!(sut(&[&config("allow-basic = true")])
+            .unwrap_err()
+            .contains("a password is required"));
+
+        // Flattens tables and dotted keys
+        let args = sut(&[
+            &config(
+                r#"
+                session.expiry = "7d"
+                [rate-limit]
+                attempts = 3
+                max-lockout = "2h"
+                "#,
+            ),
+            PWARG,
+        ])
+        .unwrap()
+        .args
+        .unwrap();
+        assert_eq!(args.session_expiry.to_string(), "1w");
+        assert_eq!(args.rate_limit_attempts, 3);
+        assert_eq!(
+            args.rate_limit_max_lockout,
+            std::time::Duration::from_secs(2 * 60 * 60)
+        );
+        assert!(sut(&[&config("[rate-limit]\nunknown = 1"), PWARG])
+            .unwrap_err()
+            .contains("unknown option 'rate-limit-unknown'"));
+        assert!(sut(&[&config("[[bind-addr]]\nport = 1"), PWARG])
+            .unwrap_err()
+            .contains("unsupported value for 'bind-addr'"));
+
+        // Rejects unknown options and invalid values
+        assert!(sut(&[&config("unknown = 1"), PWARG])
+            .unwrap_err()
+            .contains("unknown option 'unknown'"));
+        assert!(sut(&[&config("rate-limit-attempts = 0"), PWARG]).is_err());
+        assert!(sut(&[&config("not toml"), PWARG]).is_err());
+        assert!(sut(&["--config=does-not-exist.toml", PWARG]).is_err());
+    }
+
+    #[test]
+    fn test_bind_addr() {
+        use super::listen::BindAddr;
+
+        assert_eq!(
+            sut(&[PWARG]).unwrap().args.unwrap().bind_addr,
+            vec![BindAddr::Tcp("0.0.0.0:3862".parse().unwrap())]
+        );
+
+        // Accepts multiple addresses, including unix sockets
+        assert_eq!(
+            sut(&[
+                PWARG,
+                "-b",
+                "127.0.0.1:1234",
+                "--bind-addr=unix:/run/dumb-auth.sock,[::1]:5678"
+            ])
+            .unwrap()
+            .args
+            .unwrap()
+            .bind_addr,
+            vec![
+                BindAddr::Tcp("127.0.0.1:1234".parse().unwrap()),
+                BindAddr::Unix("/run/dumb-auth.sock".into()),
+                BindAddr::Tcp("[::1]:5678".parse().unwrap()),
+            ]
+        );
+
+        assert!(sut(&[PWARG, "--bind-addr=localhost"]).is_err());
+        assert!(sut(&[PWARG, "--bind-addr=unix:"]).is_err());
+    }
+
+    #[test]
+    fn test_metrics_addr() {
+        use super::listen::BindAddr;
+
+        assert_eq!(sut(&[PWARG]).unwrap().args.unwrap().metrics_addr, None);
+        assert_eq!(
+            sut(&[PWARG, "--metrics-addr=127.0.0.1:9090"])
+                .unwrap()
+                .args
+                .unwrap()
+                .metrics_addr,
+            Some(BindAddr::Tcp("127.0.0.1:9090".parse().unwrap()))
+        );
+        assert!(sut(&[PWARG, "--metrics-addr=9090"]).is_err());
+    }
+
+    #[test]
+    fn test_logging() {
+        use super::logging::LogFormat;
+
+        let args = sut(&[PWARG]).unwrap().args.unwrap();
+        assert_eq!(args.log_format, LogFormat::Text);
+        assert_eq!(args.audit_log, None);
+
+        let args = sut(&[PWARG, "--log-format=json", "--audit-log=audit.log"])
+            .unwrap()
+            .args
+            .unwrap();
+        assert_eq!(args.log_format, LogFormat::Json);
+        assert_eq!(args.audit_log, Some(PathBuf::from("audit.log")));
+
+        assert!(sut(&[PWARG, "--log-format=xml"]).is_err());
+    }
+
+    #[test]
+    fn test_socket_options() {
+        let args = sut(&[PWARG, "--socket-mode=660", "--socket-owner=www-data:1000"])
+            .unwrap()
+            .args
+            .unwrap();
+        assert_eq!(args.socket_mode, Some(0o660));
+        assert!(args.socket_owner.is_some());
+
+        assert!(sut(&[PWARG, "--socket-mode=999"]).is_err());
+        assert!(sut(&[PWARG, "--socket-mode=177777"]).is_err());
+        assert!(sut(&[PWARG, "--socket-owner=:"]).is_err());
+        assert!(sut(&[PWARG, "--socket-owner=:www-data"]).is_ok());
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_bind_unix_socket() {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        use super::listen::{BindAddr, Listener, SocketOptions};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dumb-auth.sock");
+        let options = SocketOptions {
+            mode: Some(0o600),
+            owner: None,
+        };
+
+        let listener = Listener::bind(&BindAddr::Unix(path.clone()), &options)
+            .await
+            .unwrap();
+        assert_eq!(listener.to_string(), format!("unix:{}", path.display()));
+
+        // Moved into place with the mode already set, leaving nothing else behind
+        let metadata = std::fs::symlink_metadata(&path).unwrap();
+        assert!(metadata.file_type().is_socket());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+        std::os::unix::net::UnixStream::connect(&path).unwrap();
+
+        // Replaces a stale socket, but not other files
+        drop(listener);
+        Listener::bind(&BindAddr::Unix(path.clone()), &options)
+            .await
+            .unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        assert!(Listener::bind(&BindAddr::Unix(file), &options)
+            .await
+            .is_err());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_systemd_listen_fds() {
+        use super::systemd::parse_listen_fds;
+
+        assert_eq!(parse_listen_fds(None, None, 42), Ok(0));
+        assert_eq!(parse_listen_fds(Some("42"), Some("2"), 42), Ok(2));
+
+        // Ignores fds meant for another process
+        assert_eq!(parse_listen_fds(Some("41"), Some("2"), 42), Ok(0));
+        assert_eq!(parse_listen_fds(None, Some("2"), 42), Ok(0));
+
+        assert!(parse_listen_fds(Some("42"), Some("-1"), 42).is_err());
+        assert!(parse_listen_fds(Some("42"), Some("two"), 42).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_systemd_watchdog() {
+        use std::time::Duration;
+
+        use super::systemd::parse_watchdog;
+
+        assert_eq!(parse_watchdog(None, None, 42), None);
+        assert_eq!(
+            parse_watchdog(Some("30000000"), None, 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            parse_watchdog(Some("30000000"), Some("42"), 42),
+            Some(Duration::from_secs(15))
+        );
+
+        // Ignores watchdogs for another process
+        assert_eq!(parse_watchdog(Some("30000000"), Some("41"), 42), None);
+        assert_eq!(parse_watchdog(Some("0"), None, 42), None);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_systemd_notify() {
+        use std::os::unix::net::UnixDatagram;
+
+        use super::systemd::Notifier;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let socket = UnixDatagram::bind(&path).unwrap();
+        let mut buf = [0; 256];
+        let mut recv = || {
+            let len = socket.recv(&mut buf).unwrap();
+            String::from_utf8(buf[..len].to_vec()).unwrap()
+        };
+
+        let notifier = Notifier::new(Some(path.into()));
+        notifier.ready();
+        assert_eq!(recv(), "READY=1");
+        notifier.reloading();
+        assert!(recv().starts_with("RELOADING=1\nMONOTONIC_USEC="));
+        notifier.watchdog();
+        assert_eq!(recv(), "WATCHDOG=1");
+        notifier.stopping();
+        assert_eq!(recv(), "STOPPING=1");
+
+        // Does nothing without NOTIFY_SOCKET
+        let notifier = Notifier::new(None);
+        notifier.ready();
+        assert_eq!(notifier.watchdog_interval(), None);
+    }
+
+    #[test]
+    fn test_healthcheck() {
+        use std::{
+            io::{BufRead, BufReader, Write},
+            net::TcpListener,
+            thread,
+            time::Duration,
+        };
+
+        use super::healthcheck::{check, HealthcheckUrl};
+
+        let Some(Cmd::Healthcheck(args)) = sut(&["healthcheck"]).unwrap().cmd else {
+            panic!("expected healthcheck");
+        };
+        assert_eq!(args.url, "http://127.0.0.1:3862/health".parse().unwrap());
+        assert_eq!(args.timeout, Duration::from_secs(5));
+
+        // Doesn't require or allow run args
+        assert!(sut(&[PWARG, "healthcheck"]).is_err());
+
+        assert!("http://[::1]:1234/ready".parse::<HealthcheckUrl>().is_ok());
+        assert!("http://[::1]/health".parse::<HealthcheckUrl>().is_ok());
+        assert!("http://localhost".parse::<HealthcheckUrl>().is_ok());
+        assert!("https://localhost/health"
+            .parse::<HealthcheckUrl>()
+            .is_err());
+        assert!("http://:1234/health".parse::<HealthcheckUrl>().is_err());
+        assert!("http://localhost:port/health"
+            .parse::<HealthcheckUrl>()
+            .is_err());
+
+        // Succeeds only with a 2xx response
+        for (response, ok) in [
+            ("HTTP/1.1 200 OK\r\n\r\n", true),
+            ("HTTP/1.1 204 No Content\r\n\r\n", true),
+            ("HTTP/1.1 503 Service Unavailable\r\n\r\n", false),
+            ("nonsense", false),
+        ] {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/health", listener.local_addr().unwrap());
+            let server = thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = String::new();
+                let mut reader = BufReader::new(&stream);
+                while reader.read_line(&mut request).unwrap() > 2 {}
+                assert!(request.starts_with("GET /health HTTP/1.1\r\n"));
+
+                stream.write_all(response.as_bytes()).unwrap();
+            });
+
+            let result = check(&url.parse().unwrap(), Duration::from_secs(5));
+            assert_eq!(result.is_ok(), ok, "{response}: {result:?}");
+            server.join().unwrap();
+        }
+    }
+
     #[test]
     fn test_passwd() {
         // Does not require run args
@@ -158,4 +810,137 @@ mod tests {
             .unwrap_err()
             .contains("subcommand 'passwd' cannot be used with '--password"));
     }
+
+    #[test]
+    fn test_sessions() {
+        use std::time::Duration;
+
+        use dumb_auth::AuthConfig;
+
+        use super::{
+            datastore::DatastorePath,
+            sessions::{ListArgs, RevokeArgs, SessionsCmd},
+        };
+
+        let sessions_cmd = |args: &[&str]| match sut(args).map(|cli| cli.cmd) {
+            Ok(Some(Cmd::Sessions(args))) => Ok(args.cmd),
+            Ok(cmd) => panic!("expected sessions, got {cmd:?}"),
+            Err(e) => Err(e),
+        };
+        let datastore = DatastorePath {
+            datastore: "dumb-auth.mdb".into(),
+        };
+
+        assert_eq!(
+            sessions_cmd(&["sessions", "list", "--datastore=dumb-auth.mdb"]),
+            Ok(SessionsCmd::List(ListArgs {
+                datastore: DatastorePath {
+                    datastore: "dumb-auth.mdb".into()
+                },
+                session_expiry: AuthConfig::DEFAULT_SESSION_EXPIRY,
+                session_idle_timeout: None,
+            }))
+        );
+
+        // Takes an audit log before or after the subcommand
+        for args in [
+            [
+                "sessions",
+                "--audit-log=audit.log",
+                "list",
+                "--datastore=dumb-auth.mdb",
+            ],
+            [
+                "sessions",
+                "list",
+                "--datastore=dumb-auth.mdb",
+                "--audit-log=audit.log",
+            ],
+        ] {
+            match sut(&args).unwrap().cmd {
+                Some(Cmd::Sessions(args)) => {
+                    assert_eq!(args.audit_log, Some(PathBuf::from("audit.log")))
+                }
+                cmd => panic!("expected sessions, got {cmd:?}"),
+            }
+        }
+
+        // Requires a datastore
+        assert!(sessions_cmd(&["sessions", "list"])
+            .unwrap_err()
+            .contains("--datastore"));
+
+        // Revokes by ID, all or age
+        assert_eq!(
+            sessions_cmd(&["sessions", "revoke", "--datastore=dumb-auth.mdb", "3", "5"]),
+            Ok(SessionsCmd::Revoke(RevokeArgs {
+                datastore,
+                ids: vec![3, 5],
+                all: false,
+                older_than: None,
+            }))
+        );
+        assert!(matches!(
+            sessions_cmd(&["sessions", "revoke", "--datastore=dumb-auth.mdb", "--all"]),
+            Ok(SessionsCmd::Revoke(RevokeArgs { all: true, .. }))
+        ));
+        assert!(matches!(
+            sessions_cmd(&["sessions", "revoke", "--datastore=dumb-auth.mdb", "--older-than=7d"]),
+            Ok(SessionsCmd::Revoke(RevokeArgs {
+                older_than: Some(older_than),
+                ..
+            })) if older_than == Duration::from_secs(7 * 24 * 60 * 60)
+        ));
+
+        // Requires exactly one way of choosing sessions
+        assert!(sessions_cmd(&["sessions", "revoke", "--datastore=dumb-auth.mdb"]).is_err());
+        assert!(sessions_cmd(&[
+            "sessions",
+            "revoke",
+            "--datastore=dumb-auth.mdb",
+            "--all",
+            "3"
+        ])
+        .unwrap_err()
+        .contains("cannot be used with"));
+    }
+
+    #[test]
+    fn test_datastore_migrate() {
+        use super::datastore::{DatastoreCmd, DatastorePath, MigrateArgs};
+
+        let migrate_args = |args: &[&str]| match sut(args).map(|cli| cli.cmd) {
+            Ok(Some(Cmd::Datastore(args))) => {
+                let DatastoreCmd::Migrate(args) = args.cmd;
+                Ok(args)
+            }
+            Ok(cmd) => panic!("expected datastore, got {cmd:?}"),
+            Err(e) => Err(e),
+        };
+
+        assert_eq!(
+            migrate_args(&["datastore", "migrate", "--datastore=dumb-auth.mdb"]),
+            Ok(MigrateArgs {
+                datastore: DatastorePath {
+                    datastore: "dumb-auth.mdb".into()
+                },
+                dry_run: false,
+            })
+        );
+        assert!(
+            migrate_args(&[
+                "datastore",
+                "migrate",
+                "--datastore=dumb-auth.mdb",
+                "--dry-run"
+            ])
+            .unwrap()
+            .dry_run
+        );
+
+        // Requires a datastore
+        assert!(migrate_args(&["datastore", "migrate"])
+            .unwrap_err()
+            .contains("--datastore"));
+    }
 }
@@ -1,25 +1,79 @@
-use std::{fs, net::SocketAddr, path::PathBuf};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
+use axum::http::HeaderName;
 use clap::{ArgAction, Args};
-use dumb_auth::{AppConfig, AuthConfig, Datastore, Password, ReadMode, SessionExpiry, WriteMode};
+use dumb_auth::{
+    AppConfig, AppHandle, AuthConfig, Datastore, DatastoreOptions, IdentityHeaders, Password,
+    RateLimitConfig, ReadMode, SessionExpiry, SessionId, SessionKeys, TotpSecret, WriteMode,
+};
 use password_hash::PasswordHashString;
-use tokio::{net::TcpListener, runtime::Runtime};
-use tracing::info;
+use time::Duration;
+use tokio::{
+    runtime::Runtime,
+    sync::watch,
+    task::{self, JoinSet},
+    time::{timeout_at, Instant},
+};
+use tracing::{error, info, warn};
 
-use super::common::{die, fatal};
+use super::{
+    common::{die, fatal},
+    datastore::DatastoreLocation,
+    listen::{BindAddr, Listener, SocketOptions, SocketOwner},
+    logging::LogFormat,
+    systemd::{self, Notifier},
+    Cli,
+};
 
 #[derive(Args, Debug, PartialEq)]
 #[command(next_line_help = true)]
 pub struct RunArgs {
-    /// The IP address and port to listen on.
+    /// TOML file to load options from.
+    ///
+    /// Keys are the long option names, e.g. `session-expiry = "7d"`, with arrays for options that
+    /// can be given multiple times. Tables are flattened by joining keys with `-`, so
+    /// `[rate-limit]` with `attempts = 3` sets `rate-limit-attempts`. Options given on the command
+    /// line or in the environment take precedence over the config file, except for the password
+    /// which may only be set in one place.
+    ///
+    /// Send SIGHUP to reload the config file and any password, TOTP secret, session key or revoked
+    /// sessions files. Only the password, TOTP secret, auth methods and session options take
+    /// effect without restarting.
+    #[arg(short, long, env = "DUMB_AUTH_CONFIG", hide_env = true)]
+    pub config: Option<PathBuf>,
+    /// The IP address and port, or `unix:<path>` for a Unix domain socket, to listen on.
+    ///
+    /// Can be given multiple times to listen on several addresses. Ignored if sockets are passed by
+    /// systemd socket activation.
     #[arg(
         short,
         long,
         env = "DUMB_AUTH_BIND_ADDR",
         hide_env = true,
+        value_delimiter = ',',
         default_value = "0.0.0.0:3862"
     )]
-    pub bind_addr: SocketAddr,
+    pub bind_addr: Vec<BindAddr>,
+    /// File mode to set on Unix domain sockets, in octal, e.g. "660".
+    #[arg(
+        long,
+        env = "DUMB_AUTH_SOCKET_MODE",
+        hide_env = true,
+        value_parser = parse_mode
+    )]
+    pub socket_mode: Option<u32>,
+    /// Owner to set on Unix domain sockets, as "user", "user:group" or ":group".
+    #[arg(long, env = "DUMB_AUTH_SOCKET_OWNER", hide_env = true)]
+    pub socket_owner: Option<SocketOwner>,
+    /// The IP address and port, or `unix:<path>`, to serve Prometheus metrics on at `/metrics`.
+    ///
+    /// Metrics are only served on this address, so it can be kept private from the reverse proxy.
+    #[arg(long, env = "DUMB_AUTH_METRICS_ADDR", hide_env = true)]
+    pub metrics_addr: Option<BindAddr>,
     /// The base path for public routes.
     #[arg(
         long,
@@ -29,6 +83,40 @@ pub struct RunArgs {
         default_value = AppConfig::DEFAULT_PUBLIC_PATH
     )]
     pub public_path: String,
+    /// The path prefix Envoy's `ext_authz` filter sends requests to.
+    #[arg(
+        long,
+        env = "DUMB_AUTH_EXT_AUTHZ_PREFIX",
+        hide_env = true,
+        value_parser = parse_prefix_path,
+        default_value = AppConfig::DEFAULT_EXT_AUTHZ_PREFIX
+    )]
+    pub ext_authz_prefix: String,
+
+    /// Format of log lines.
+    ///
+    /// Log levels can be set with the `DUMB_AUTH_LOG` environment variable, e.g. "debug".
+    #[arg(
+        help_heading = "Logging",
+        long,
+        env = "DUMB_AUTH_LOG_FORMAT",
+        hide_env = true,
+        value_enum,
+        default_value_t = LogFormat::Text
+    )]
+    pub log_format: LogFormat,
+    /// File to append audit events to, as one JSON object per line.
+    ///
+    /// Audit events are logins, failed logins, logouts, session creation, expiry and revocation,
+    /// and denied auth requests, with the client IP, user agent and original URI where known. They
+    /// are written to the main log instead if this isn't set.
+    #[arg(
+        help_heading = "Logging",
+        long,
+        env = "DUMB_AUTH_AUDIT_LOG",
+        hide_env = true
+    )]
+    pub audit_log: Option<PathBuf>,
 
     /// Number of worker threads to use, or 0 to # of cores.
     ///
@@ -57,6 +145,19 @@ pub struct RunArgs {
         group = "threads_arg"
     )]
     pub single_thread: bool,
+    /// How long to wait for in-flight requests and pending datastore writes when shutting down.
+    ///
+    /// After receiving SIGTERM or SIGINT, no new connections are accepted, and dumb-auth exits once
+    /// all in-flight requests have finished and the datastore has been closed, or after this long.
+    #[arg(
+        help_heading = "Runtime",
+        long,
+        env = "DUMB_AUTH_SHUTDOWN_TIMEOUT",
+        hide_env = true,
+        value_parser = parse_std_duration,
+        default_value = "10s"
+    )]
+    pub shutdown_timeout: std::time::Duration,
 
     /// The password used to authenticate.
     #[arg(
@@ -65,7 +166,12 @@ pub struct RunArgs {
         env = "DUMB_AUTH_PASSWORD",
         hide_env = true,
         group = "password_arg",
-        required = true
+        required_unless_present_any = [
+            "config",
+            "password_file",
+            "password_hash",
+            "password_hash_file"
+        ]
     )]
     pub password: Option<String>,
     /// File containing the password used to authenticate.
@@ -98,6 +204,28 @@ pub struct RunArgs {
     )]
     pub password_hash_file: Option<PathBuf>,
 
+    /// Base32 encoded secret for TOTP codes required when logging in.
+    ///
+    /// Use the `totp` subcommand to generate a secret. This only applies to interactive logins,
+    /// HTTP Basic and Bearer authentication still only require the password.
+    #[arg(
+        help_heading = "Two-Factor",
+        long,
+        env = "DUMB_AUTH_TOTP_SECRET",
+        hide_env = true,
+        group = "totp_arg"
+    )]
+    pub totp_secret: Option<String>,
+    /// File containing the base32 encoded secret for TOTP codes required when logging in.
+    #[arg(
+        help_heading = "Two-Factor",
+        long,
+        env = "DUMB_AUTH_TOTP_SECRET_FILE",
+        hide_env = true,
+        group = "totp_arg"
+    )]
+    pub totp_secret_file: Option<PathBuf>,
+
     /// Allow using HTTP Basic authentication to authenticate.
     ///
     /// When authenticating with HTTP Basic authentication the username is ignored (i.e. it can be
@@ -133,6 +261,54 @@ pub struct RunArgs {
         default_missing_value = "true",
     )]
     pub allow_session: bool,
+    /// Add headers identifying how the request was authenticated to valid responses.
+    ///
+    /// `--auth-method-header` is always set to `basic`, `bearer` or `session`.
+    /// `--auth-session-id-header` is set to the session's ID for sessions, and `--auth-user-header`
+    /// to the (unchecked) username for HTTP Basic authentication.
+    ///
+    /// Make sure your reverse proxy removes these headers from client requests before passing them
+    /// on, otherwise clients can set them themselves.
+    #[arg(
+        help_heading = "Auth Methods",
+        long,
+        env = "DUMB_AUTH_IDENTITY_HEADERS",
+        hide_env = true,
+        action = ArgAction::Set,
+        hide_possible_values = true,
+        default_value_t = false,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+    )]
+    pub identity_headers: bool,
+    /// Name of the header set to the auth method, if `--identity-headers` is enabled.
+    #[arg(
+        help_heading = "Auth Methods",
+        long,
+        env = "DUMB_AUTH_AUTH_METHOD_HEADER",
+        hide_env = true,
+        default_value = "X-Auth-Method"
+    )]
+    pub auth_method_header: HeaderName,
+    /// Name of the header set to the session ID, if `--identity-headers` is enabled.
+    #[arg(
+        help_heading = "Auth Methods",
+        long,
+        env = "DUMB_AUTH_AUTH_SESSION_ID_HEADER",
+        hide_env = true,
+        default_value = "X-Auth-Session-Id"
+    )]
+    pub auth_session_id_header: HeaderName,
+    /// Name of the header set to the HTTP Basic username, if `--identity-headers` is enabled.
+    #[arg(
+        help_heading = "Auth Methods",
+        long,
+        env = "DUMB_AUTH_AUTH_USER_HEADER",
+        hide_env = true,
+        default_value = "X-Auth-User"
+    )]
+    pub auth_user_header: HeaderName,
 
     /// Name of the session cookie.
     #[arg(
@@ -176,21 +352,168 @@ pub struct RunArgs {
         default_value_t = AuthConfig::DEFAULT_SESSION_EXPIRY
     )]
     pub session_expiry: SessionExpiry,
+    /// Expire sessions that haven't been used for this long, e.g. "30m".
+    ///
+    /// Sessions are renewed as they're used, up to `--session-expiry` after they were created, and
+    /// the cookie is re-issued with each renewal so the reverse proxy must pass the `Set-Cookie`
+    /// header from auth responses on to the browser. Renewals are only recorded about once a
+    /// minute, or every half of the idle timeout if shorter.
+    #[arg(
+        help_heading = "Session Config",
+        long,
+        env = "DUMB_AUTH_SESSION_IDLE_TIMEOUT",
+        hide_env = true,
+        value_parser = parse_positive_duration
+    )]
+    pub session_idle_timeout: Option<Duration>,
+    /// File of keys to sign stateless session cookies with, instead of storing sessions in the
+    /// datastore.
+    ///
+    /// Signed sessions can be checked by multiple instances of dumb-auth sharing the same keys,
+    /// without sharing a datastore. They can't be listed or deleted, so logging out only removes
+    /// the cookie, see `--revoked-sessions-file`.
+    ///
+    /// The file has one base64 encoded key of at least 256 bits per line, e.g. from
+    /// `openssl rand -base64 32`. New sessions are signed with the last key, while sessions signed
+    /// with any of the keys are accepted, so keys can be rotated by adding a new key at the end
+    /// and later removing old keys.
+    #[arg(
+        help_heading = "Session Config",
+        long,
+        env = "DUMB_AUTH_SESSION_KEY_FILE",
+        hide_env = true
+    )]
+    pub session_key_file: Option<PathBuf>,
+    /// File of signed session IDs to reject, one per line.
+    ///
+    /// Session IDs are in the audit log, and the `--auth-session-id-header` header if
+    /// `--identity-headers` is enabled. Revoked sessions can be removed from the file after they
+    /// expire.
+    #[arg(
+        help_heading = "Session Config",
+        long,
+        env = "DUMB_AUTH_REVOKED_SESSIONS_FILE",
+        hide_env = true,
+        requires = "session_key_file"
+    )]
+    pub revoked_sessions_file: Option<PathBuf>,
+    /// Additional hosts that may be redirected to after logging in.
+    ///
+    /// By default, users are only redirected to relative URIs, URIs on the same host as the login
+    /// page, or hosts within `--session-cookie-domain`. Any other `redirect_to` is ignored.
+    #[arg(
+        help_heading = "Session Config",
+        long = "allowed-redirect-host",
+        env = "DUMB_AUTH_ALLOWED_REDIRECT_HOSTS",
+        hide_env = true,
+        value_delimiter = ','
+    )]
+    pub allowed_redirect_hosts: Vec<String>,
+    /// How often to remove expired sessions from the datastore, or "0s" to disable.
+    ///
+    /// Expired sessions are always rejected, but otherwise are only removed when they are next
+    /// used. Periodically removing them stops abandoned sessions from accumulating.
+    #[arg(
+        help_heading = "Session Config",
+        long,
+        env = "DUMB_AUTH_SESSION_SWEEP_INTERVAL",
+        hide_env = true,
+        value_parser = parse_duration,
+        default_value = "1h"
+    )]
+    pub session_sweep_interval: Duration,
+
+    /// Limit failed password attempts from each client.
+    ///
+    /// After `--rate-limit-attempts` failures, the client is locked out for `--rate-limit-lockout`,
+    /// doubling with each subsequent failure up to `--rate-limit-max-lockout`. While locked out all
+    /// login and password auth attempts get a 429 response with a `Retry-After` header. Requests
+    /// using a session cookie are never limited.
+    ///
+    /// Behind a reverse proxy, also set `--client-ip-header`, otherwise every client has the
+    /// proxy's address and one client's failures lock out everyone. It's required when listening
+    /// on a Unix socket, which has no client address.
+    ///
+    /// Note: nginx treats a 429 from `auth_request` as an error and responds with a 500.
+    #[arg(
+        help_heading = "Rate Limiting",
+        long,
+        env = "DUMB_AUTH_RATE_LIMIT",
+        hide_env = true,
+        action = ArgAction::Set,
+        hide_possible_values = true,
+        default_value_t = false,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+    )]
+    pub rate_limit: bool,
+    /// Number of failed attempts allowed before a client is locked out.
+    #[arg(
+        help_heading = "Rate Limiting",
+        long,
+        env = "DUMB_AUTH_RATE_LIMIT_ATTEMPTS",
+        hide_env = true,
+        value_parser = clap::value_parser!(u32).range(1..),
+        default_value_t = RateLimitConfig::DEFAULT_MAX_ATTEMPTS
+    )]
+    pub rate_limit_attempts: u32,
+    /// How long a client is initially locked out for.
+    #[arg(
+        help_heading = "Rate Limiting",
+        long,
+        env = "DUMB_AUTH_RATE_LIMIT_LOCKOUT",
+        hide_env = true,
+        value_parser = parse_std_duration,
+        default_value = "1m"
+    )]
+    pub rate_limit_lockout: std::time::Duration,
+    /// The longest a client can be locked out for.
+    ///
+    /// Failed attempts are also forgotten after this long without any more failures.
+    #[arg(
+        help_heading = "Rate Limiting",
+        long,
+        env = "DUMB_AUTH_RATE_LIMIT_MAX_LOCKOUT",
+        hide_env = true,
+        value_parser = parse_std_duration,
+        default_value = "1h"
+    )]
+    pub rate_limit_max_lockout: std::time::Duration,
+    /// Header containing the client's IP address, e.g. "X-Real-IP" or "X-Forwarded-For".
+    ///
+    /// Only set this if the header is always set by your reverse proxy, otherwise clients can
+    /// choose their own IP address. If not set, the address of the connecting client is used,
+    /// which will be the reverse proxy itself.
+    #[arg(
+        help_heading = "Rate Limiting",
+        long,
+        env = "DUMB_AUTH_CLIENT_IP_HEADER",
+        hide_env = true
+    )]
+    pub client_ip_header: Option<HeaderName>,
 
     /// File to store sessions.
     ///
     /// If not set, sessions will only be kept in memory and will be lost when dumb-auth is
     /// restarted. Using a datastore allows sessions to be remembered across restarts.
     ///
-    /// Warning: The file may contain sensitive data (but not passwords). Make sure the correct
-    /// permissions are set so that the data can't be read by other processes or users.
+    /// Sessions are stored in an LMDB file by default. Prefix the path with "sqlite:" to use a
+    /// SQLite database instead, e.g. "sqlite:/var/lib/dumb-auth/sessions.db", which can be
+    /// inspected with standard tools. Unlike LMDB, SQLite only needs file locking, so it can be
+    /// used on network filesystems where locking works.
+    ///
+    /// Warning: The file may contain sensitive data, such as client IPs (but not passwords or
+    /// session secrets, which are hashed). Make sure the correct permissions are set so that the
+    /// data can't be read by other processes or users.
     #[arg(
         help_heading = "Datastore",
         long,
         env = "DUMB_AUTH_DATASTORE",
         hide_env = true
     )]
-    pub datastore: Option<PathBuf>,
+    pub datastore: Option<DatastoreLocation>,
+    /// Where datastore reads are run.
     #[arg(
         help_heading = "Datastore",
         long,
@@ -209,6 +532,20 @@ pub struct RunArgs {
         default_value_t = Default::default(),
     )]
     pub datastore_write_mode: WriteMode,
+    /// Maximum size of the datastore file, e.g. "64MiB", "1GiB".
+    ///
+    /// The datastore file starts small and grows automatically as more sessions are stored, up to
+    /// this size. Once it is full, new sessions can't be created until old ones expire. Only
+    /// applies to LMDB datastores.
+    #[arg(
+        help_heading = "Datastore",
+        long,
+        env = "DUMB_AUTH_DATASTORE_MAX_SIZE",
+        hide_env = true,
+        value_parser = parse_size,
+        default_value = "1GiB"
+    )]
+    pub datastore_max_size: usize,
 }
 
 impl RunArgs {
@@ -229,58 +566,135 @@ impl RunArgs {
             .unwrap_or_else(|e| fatal("creating runtime", e))
     }
 
-    pub fn password(&self) -> Password {
+    pub fn password(&self) -> Result<Password, String> {
         let read_file = |path| {
-            let mut string =
-                fs::read_to_string(path).unwrap_or_else(|e| fatal("reading password/hash file", e));
-
-            // Trim final \n or \r\n
-            if string.ends_with('\n') {
-                string.pop();
-                if string.ends_with('\r') {
-                    string.pop();
-                }
-            }
-
-            string
+            read_secret_file(path).map_err(|e| format!("Error reading password/hash file: {e}"))
         };
 
         let parse_hash = |hash| {
-            PasswordHashString::new(hash).unwrap_or_else(|e| fatal("parsing password hash", e))
+            PasswordHashString::new(hash).map_err(|e| format!("Error parsing password hash: {e}"))
         };
 
         let password = if let Some(plain) = &self.password {
             Password::Plain(plain.clone())
         } else if let Some(path) = &self.password_file {
-            Password::Plain(read_file(path))
+            Password::Plain(read_file(path)?)
         } else if let Some(hash) = &self.password_hash {
-            Password::Hash(parse_hash(hash))
+            Password::Hash(parse_hash(hash)?)
         } else if let Some(path) = &self.password_hash_file {
-            Password::Hash(parse_hash(&read_file(path)))
+            Password::Hash(parse_hash(&read_file(path)?)?)
         } else {
             unreachable!()
         };
 
         if let Password::Plain(password) = &password {
             if password.is_empty() {
-                die("Password cannot be empty");
+                return Err("Password cannot be empty".into());
             }
         }
 
-        password
+        Ok(password)
+    }
+
+    pub fn totp_secret(&self) -> Result<Option<TotpSecret>, String> {
+        let secret = if let Some(secret) = &self.totp_secret {
+            secret.clone()
+        } else if let Some(path) = &self.totp_secret_file {
+            read_secret_file(path).map_err(|e| format!("Error reading TOTP secret file: {e}"))?
+        } else {
+            return Ok(None);
+        };
+
+        secret
+            .parse()
+            .map(Some)
+            .map_err(|e| format!("Error parsing TOTP secret: {e}"))
+    }
+
+    pub fn session_keys(&self) -> Result<Option<SessionKeys>, String> {
+        let Some(path) = &self.session_key_file else {
+            return Ok(None);
+        };
+
+        fs::read_to_string(path)
+            .map_err(|e| format!("Error reading session key file: {e}"))?
+            .parse()
+            .map(Some)
+            .map_err(|e| format!("Error parsing session key file: {e}"))
+    }
+
+    pub fn revoked_sessions(&self) -> Result<Vec<SessionId>, String> {
+        let Some(path) = &self.revoked_sessions_file else {
+            return Ok(Vec::new());
+        };
+
+        fs::read_to_string(path)
+            .map_err(|e| format!("Error reading revoked sessions file: {e}"))?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                line.parse().map(SessionId).map_err(|_| {
+                    format!("Error parsing revoked sessions file: invalid session ID '{line}'")
+                })
+            })
+            .collect()
+    }
+
+    /// Build the `AuthConfig`, reading the password, TOTP secret and session keys from files if
+    /// needed.
+    pub fn auth_config(&self) -> Result<AuthConfig, String> {
+        Ok(AuthConfig {
+            password: self.password()?,
+            totp_secret: self.totp_secret()?,
+            allow_basic: self.allow_basic,
+            allow_bearer: self.allow_bearer,
+            allow_session: self.allow_session,
+            identity_headers: self.identity_headers.then(|| IdentityHeaders {
+                method: self.auth_method_header.clone(),
+                session_id: self.auth_session_id_header.clone(),
+                user: self.auth_user_header.clone(),
+            }),
+            session_cookie_name: self.session_cookie_name.clone(),
+            session_cookie_domain: self.session_cookie_domain.clone(),
+            session_expiry: self.session_expiry,
+            session_idle_timeout: self.session_idle_timeout,
+            session_keys: self.session_keys()?,
+            revoked_sessions: self.revoked_sessions()?,
+            allowed_redirect_hosts: self.allowed_redirect_hosts.clone(),
+        })
     }
 
     pub async fn datastore(&self) -> Datastore {
         match &self.datastore {
-            Some(path) => {
-                Datastore::open_with(path, self.datastore_read_mode, self.datastore_write_mode)
-                    .unwrap_or_else(|e| fatal("opening datastore", e))
-            }
+            Some(location) => location
+                .open(DatastoreOptions {
+                    read_mode: self.datastore_read_mode,
+                    write_mode: self.datastore_write_mode,
+                    max_size: self.datastore_max_size,
+                    // Unlike other commands, the server migrates older datastores on startup
+                    migrate: true,
+                })
+                .unwrap_or_else(|e| fatal("opening datastore", e)),
             None => Datastore::new_in_memory(),
         }
     }
 }
 
+fn read_secret_file(path: &Path) -> io::Result<String> {
+    let mut string = fs::read_to_string(path)?;
+
+    // Trim final \n or \r\n
+    if string.ends_with('\n') {
+        string.pop();
+        if string.ends_with('\r') {
+            string.pop();
+        }
+    }
+
+    Ok(string)
+}
+
 fn parse_base_path(s: &str) -> Result<String, String> {
     if s.is_empty() {
         Err("base path must not be empty".into())
@@ -301,27 +715,293 @@ fn parse_base_path(s: &str) -> Result<String, String> {
     }
 }
 
+fn parse_prefix_path(s: &str) -> Result<String, String> {
+    if s == "/" {
+        Err("prefix path must not be '/'".into())
+    } else {
+        parse_base_path(s)
+    }
+}
+
+fn parse_mode(s: &str) -> Result<u32, String> {
+    match u32::from_str_radix(s, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err("mode must be in octal, e.g. 660".into()),
+    }
+}
+
+pub(super) fn parse_duration(s: &str) -> Result<Duration, String> {
+    duration_str::parse_time(s)
+}
+
+fn parse_positive_duration(s: &str) -> Result<Duration, String> {
+    match parse_duration(s)? {
+        duration if duration.is_positive() => Ok(duration),
+        _ => Err("duration must be greater than 0".into()),
+    }
+}
+
+pub(super) fn parse_std_duration(s: &str) -> Result<std::time::Duration, String> {
+    duration_str::parse_std(s)
+}
+
+fn parse_size(s: &str) -> Result<usize, String> {
+    const MIN_SIZE: usize = 1024 * 1024;
+
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let number = number
+        .parse::<usize>()
+        .map_err(|_| "size must start with a number".to_string())?;
+    let multiplier: usize = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1024,
+        "m" | "mib" => 1024 * 1024,
+        "g" | "gib" => 1024 * 1024 * 1024,
+        _ => {
+            return Err(format!(
+                "unknown size unit '{}', expected KiB, MiB or GiB",
+                unit
+            ))
+        }
+    };
+
+    match number.checked_mul(multiplier) {
+        Some(size) if size >= MIN_SIZE => Ok(size),
+        Some(_) => Err("size must be at least 1MiB".into()),
+        None => Err("size is too large".into()),
+    }
+}
+
 pub fn run(args: RunArgs) {
+    // Must be taken before the runtime starts any other threads
+    let listen_fds = systemd::listen_fds().unwrap_or_else(|e| die(&e));
+
     args.runtime().block_on(async {
-        let password = args.password();
+        let notifier = Arc::new(Notifier::from_env());
+        let auth_config = args.auth_config().unwrap_or_else(|e| die(&e));
+        // Unix sockets have no peer address, so clients can only be told apart by the header
+        let needs_client_ip_header = args.rate_limit && args.client_ip_header.is_none();
+        let check_rate_limit = move |is_unix: bool| {
+            if is_unix && needs_client_ip_header {
+                die("--rate-limit requires --client-ip-header when listening on a Unix socket");
+            }
+        };
+        check_rate_limit(
+            listen_fds.is_empty()
+                && args
+                    .bind_addr
+                    .iter()
+                    .any(|addr| matches!(addr, BindAddr::Unix(_))),
+        );
+
         let datastore = args.datastore().await;
+        let closing_event = datastore.closing_event();
         let config = dumb_auth::AppConfig {
             public_path: args.public_path,
-            auth_config: AuthConfig {
-                password,
-                allow_basic: args.allow_basic,
-                allow_bearer: args.allow_bearer,
-                allow_session: args.allow_session,
-                session_cookie_name: args.session_cookie_name,
-                session_cookie_domain: args.session_cookie_domain,
-                session_expiry: args.session_expiry,
-            },
+            session_sweep_interval: Some(args.session_sweep_interval)
+                .filter(|interval| !interval.is_zero()),
+            rate_limit: args.rate_limit.then_some(RateLimitConfig {
+                max_attempts: args.rate_limit_attempts,
+                lockout: args.rate_limit_lockout,
+                max_lockout: args.rate_limit_max_lockout,
+            }),
+            client_ip_header: args.client_ip_header,
+            ext_authz_prefix: args.ext_authz_prefix,
+            auth_config,
+        };
+
+        let (app, handle) = dumb_auth::app_with_handle(config, datastore);
+        if let Some(sweeper) = handle.session_sweeper() {
+            tokio::spawn(sweeper);
+        }
+
+        let socket_options = SocketOptions {
+            mode: args.socket_mode,
+            owner: args.socket_owner,
+        };
+        let mut listeners = Vec::new();
+        if listen_fds.is_empty() {
+            for bind_addr in &args.bind_addr {
+                listeners.push(
+                    Listener::bind(bind_addr, &socket_options)
+                        .await
+                        .unwrap_or_else(|e| fatal(&format!("listening on {}", bind_addr), e)),
+                );
+            }
+        } else {
+            info!(
+                "Using {} socket(s) from systemd instead of --bind-addr",
+                listen_fds.len()
+            );
+
+            for fd in listen_fds {
+                let listener = systemd::listener(fd).unwrap_or_else(|e| die(&e));
+                check_rate_limit(listener.is_unix());
+                listeners.push(listener);
+            }
+        }
+
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let mut servers = JoinSet::new();
+
+        let wait_for_shutdown = |mut shutdown_rx: watch::Receiver<bool>| async move {
+            let _ = shutdown_rx.wait_for(|shutdown| *shutdown).await;
         };
 
-        let app = dumb_auth::app(config, datastore);
+        for listener in listeners {
+            info!("Listening for requests on {}", listener);
+            servers.spawn(listener.serve(app.clone(), wait_for_shutdown(shutdown_rx.clone())));
+        }
+        drop(app);
+
+        if let Some(metrics_addr) = &args.metrics_addr {
+            let listener = Listener::bind(metrics_addr, &socket_options)
+                .await
+                .unwrap_or_else(|e| fatal(&format!("listening on {}", metrics_addr), e));
+            info!("Serving metrics on {}/metrics", listener);
+            servers.spawn(
+                listener.serve(handle.metrics_app(), wait_for_shutdown(shutdown_rx.clone())),
+            );
+        }
+
+        // Listen for SIGHUP before signalling ready, as it would otherwise kill the server if a
+        // reload is sent straight away
+        #[cfg(unix)]
+        let reload_task = {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let hangup =
+                signal(SignalKind::hangup()).unwrap_or_else(|e| fatal("listening for SIGHUP", e));
+            tokio::spawn(reload_on_hangup(hangup, handle, notifier.clone()))
+        };
+        #[cfg(not(unix))]
+        let reload_task = tokio::spawn(reload_on_hangup(handle, notifier.clone()));
 
-        let listener = TcpListener::bind(&args.bind_addr).await.unwrap();
-        info!("Listening for requests on http://{}", &args.bind_addr);
-        axum::serve(listener, app).await.unwrap();
+        notifier.ready();
+        let watchdog_task = notifier
+            .watchdog_interval()
+            .map(|interval| tokio::spawn(watchdog(notifier.clone(), interval)));
+
+        tokio::select! {
+            Some(result) = servers.join_next() => match result {
+                Ok(result) => return result.unwrap_or_else(|e| fatal("serving requests", e)),
+                Err(e) => fatal("serving requests", e),
+            },
+            _ = shutdown_signal() => {}
+        }
+
+        notifier.stopping();
+        let _ = shutdown_tx.send(true);
+        let deadline = Instant::now() + args.shutdown_timeout;
+
+        let servers_done = timeout_at(deadline, async {
+            while let Some(result) = servers.join_next().await {
+                match result {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => error!("Error serving requests: {e}"),
+                    Err(e) => error!("Error serving requests: {e}"),
+                }
+            }
+        });
+        if servers_done.await.is_err() {
+            warn!("Timed out waiting for in-flight requests to finish");
+            servers.abort_all();
+        }
+
+        if let Some(watchdog_task) = watchdog_task {
+            watchdog_task.abort();
+        }
+
+        // Drop the last reference to the app, and so the datastore
+        reload_task.abort();
+        let _ = reload_task.await;
+
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let closed = task::spawn_blocking(move || closing_event.wait_timeout(timeout))
+            .await
+            .unwrap_or(false);
+        if closed {
+            info!("Shut down");
+        } else {
+            warn!("Timed out waiting for datastore to close");
+        }
     });
 }
+
+/// Wait for SIGTERM or SIGINT.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate =
+            signal(SignalKind::terminate()).unwrap_or_else(|e| fatal("listening for SIGTERM", e));
+        let mut interrupt =
+            signal(SignalKind::interrupt()).unwrap_or_else(|e| fatal("listening for SIGINT", e));
+
+        tokio::select! {
+            _ = terminate.recv() => info!("Received SIGTERM, shutting down"),
+            _ = interrupt.recv() => info!("Received SIGINT, shutting down"),
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c()
+            .await
+            .unwrap_or_else(|e| fatal("listening for Ctrl+C", e));
+        info!("Received Ctrl+C, shutting down");
+    }
+}
+
+/// Notify the systemd watchdog every `interval` until aborted.
+async fn watchdog(notifier: Arc<Notifier>, interval: std::time::Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        notifier.watchdog();
+    }
+}
+
+/// Reload the `AuthConfig` whenever SIGHUP is received on `hangup`.
+///
+/// The args (and config file) are parsed again, and password and TOTP secret files are re-read.
+/// Other options can't be changed without restarting.
+#[cfg(unix)]
+async fn reload_on_hangup(
+    mut hangup: tokio::signal::unix::Signal,
+    handle: AppHandle,
+    notifier: Arc<Notifier>,
+) {
+    while hangup.recv().await.is_some() {
+        info!("Received SIGHUP, reloading config");
+        notifier.reloading();
+
+        match reload_auth_config() {
+            Ok(auth_config) => {
+                handle.set_auth_config(auth_config);
+                info!("Reloaded config");
+            }
+            Err(e) => error!("Error reloading config, keeping current config: {e}"),
+        }
+
+        notifier.ready();
+    }
+}
+
+#[cfg(not(unix))]
+async fn reload_on_hangup(_handle: AppHandle, _notifier: Arc<Notifier>) {}
+
+#[cfg(unix)]
+fn reload_auth_config() -> Result<AuthConfig, String> {
+    let cli = Cli::try_parse_with_config(env::args_os()).map_err(|e| {
+        // Only keep the error itself, not the usage
+        let message = e.to_string();
+        let message = message.lines().next().unwrap_or_default();
+        message.trim_start_matches("error: ").to_string()
+    })?;
+
+    cli.args
+        .ok_or_else(|| "Missing run args".to_string())?
+        .auth_config()
+}
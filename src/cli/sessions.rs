@@ -0,0 +1,218 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use clap::{ArgGroup, Args, Subcommand};
+use dumb_auth::{AuthConfig, SessionExpiry, SessionId, SessionInfo};
+use duration_str::HumanFormat;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use super::{
+    common::{die, fatal},
+    datastore::DatastorePath,
+    run::{parse_duration, parse_std_duration},
+};
+
+/// List or revoke sessions in a datastore.
+///
+/// Safe to use while dumb-auth is running with the same datastore, changes take effect
+/// immediately.
+#[derive(Args, Debug, PartialEq)]
+pub struct SessionsArgs {
+    #[command(subcommand)]
+    pub cmd: SessionsCmd,
+    /// File to append audit events to, as given to `dumb-auth --audit-log`.
+    ///
+    /// Revoking sessions is recorded there, otherwise in the log on stderr.
+    #[arg(long, global = true, env = "DUMB_AUTH_AUDIT_LOG", hide_env = true)]
+    pub audit_log: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq, Subcommand)]
+pub enum SessionsCmd {
+    List(ListArgs),
+    Revoke(RevokeArgs),
+}
+
+/// List sessions, including expired sessions not yet removed.
+///
+/// The client IP, login method and user agent are from when the session was created. Idle time is
+/// only accurate to about a minute.
+#[derive(Args, Debug, PartialEq)]
+pub struct ListArgs {
+    #[command(flatten)]
+    pub datastore: DatastorePath,
+    /// Session expiry used to show when sessions expire, as given to `dumb-auth --session-expiry`.
+    #[arg(
+        long,
+        env = "DUMB_AUTH_SESSION_EXPIRY",
+        hide_env = true,
+        default_value_t = AuthConfig::DEFAULT_SESSION_EXPIRY
+    )]
+    pub session_expiry: SessionExpiry,
+    /// Session idle timeout used to show when sessions expire, as given to
+    /// `dumb-auth --session-idle-timeout`.
+    #[arg(
+        long,
+        env = "DUMB_AUTH_SESSION_IDLE_TIMEOUT",
+        hide_env = true,
+        value_parser = parse_duration
+    )]
+    pub session_idle_timeout: Option<time::Duration>,
+}
+
+/// Revoke sessions, logging out any browsers using them.
+#[derive(Args, Debug, PartialEq)]
+#[command(group(ArgGroup::new("sessions").required(true)))]
+pub struct RevokeArgs {
+    #[command(flatten)]
+    pub datastore: DatastorePath,
+    /// IDs of the sessions to revoke.
+    #[arg(group = "sessions")]
+    pub ids: Vec<u64>,
+    /// Revoke all sessions.
+    #[arg(long, group = "sessions")]
+    pub all: bool,
+    /// Revoke sessions created longer ago than this, e.g. "7d".
+    #[arg(long, group = "sessions", value_parser = parse_std_duration)]
+    pub older_than: Option<Duration>,
+}
+
+pub fn sessions(args: SessionsArgs) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap_or_else(|e| fatal("starting runtime", e));
+
+    runtime.block_on(async {
+        match args.cmd {
+            SessionsCmd::List(args) => list(args).await,
+            SessionsCmd::Revoke(args) => revoke(args).await,
+        }
+    });
+}
+
+async fn list(args: ListArgs) {
+    let datastore = args.datastore.open();
+    let sessions = datastore
+        .list_sessions()
+        .await
+        .unwrap_or_else(|e| fatal("listing sessions", e));
+
+    let now = SystemTime::now();
+    let rows = sessions
+        .iter()
+        .map(|session| format_session(session, &args, now))
+        .collect::<Vec<_>>();
+
+    print_table(
+        &[
+            "ID",
+            "CREATED",
+            "AGE",
+            "IDLE",
+            "EXPIRES",
+            "CLIENT IP",
+            "LOGIN",
+            "USER AGENT",
+        ],
+        &rows,
+    );
+}
+
+async fn revoke(args: RevokeArgs) {
+    let datastore = args.datastore.open();
+    let mut missing = Vec::new();
+
+    let count = if args.all {
+        datastore.revoke_sessions(|_| true).await
+    } else if let Some(older_than) = args.older_than {
+        datastore
+            .revoke_sessions(move |session| age(session, SystemTime::now()) > older_than)
+            .await
+    } else {
+        let mut count = 0;
+        for id in args.ids {
+            match datastore.revoke_session(SessionId(id)).await {
+                Ok(true) => count += 1,
+                Ok(false) => missing.push(id.to_string()),
+                Err(e) => fatal("revoking session", e),
+            }
+        }
+        Ok(count)
+    }
+    .unwrap_or_else(|e| fatal("revoking sessions", e));
+
+    println!("Revoked {} session(s)", count);
+
+    if !missing.is_empty() {
+        die(&format!("Session(s) not found: {}", missing.join(", ")));
+    }
+}
+
+fn format_session(session: &SessionInfo, args: &ListArgs, now: SystemTime) -> Vec<String> {
+    let expires = match session.expires(args.session_expiry, args.session_idle_timeout) {
+        None => "-".to_string(),
+        Some(expires) if expires <= now => format!("{} (expired)", format_time(expires)),
+        Some(expires) => format_time(expires),
+    };
+
+    let idle = now.duration_since(session.last_used).unwrap_or_default();
+
+    vec![
+        session.id.to_string(),
+        format_time(session.created),
+        format_duration(age(session, now)),
+        format_duration(idle),
+        expires,
+        or_dash(session.client_ip),
+        or_dash(session.login_method),
+        or_dash(session.user_agent.as_deref()),
+    ]
+}
+
+fn or_dash(value: Option<impl ToString>) -> String {
+    value.map_or_else(|| "-".into(), |value| value.to_string())
+}
+
+fn format_duration(duration: Duration) -> String {
+    Duration::from_secs(duration.as_secs()).human_format()
+}
+
+fn age(session: &SessionInfo, now: SystemTime) -> Duration {
+    now.duration_since(session.created).unwrap_or_default()
+}
+
+fn format_time(time: SystemTime) -> String {
+    OffsetDateTime::from(time)
+        .replace_nanosecond(0)
+        .ok()
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or_else(|| "?".into())
+}
+
+fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    let widths = header
+        .iter()
+        .enumerate()
+        .map(|(i, title)| {
+            rows.iter()
+                .map(|row| row[i].len())
+                .fold(title.len(), usize::max)
+        })
+        .collect::<Vec<_>>();
+
+    let print_row = |cells: &mut dyn Iterator<Item = &str>| {
+        let line = cells
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    };
+
+    print_row(&mut header.iter().copied());
+    for row in rows {
+        print_row(&mut row.iter().map(String::as_str));
+    }
+}
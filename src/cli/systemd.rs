@@ -0,0 +1,280 @@
+//! Support for running as a systemd service with `Type=notify` and socket activation.
+//!
+//! See `sd_listen_fds(3)` and `sd_notify(3)`.
+
+#[cfg(unix)]
+pub use self::unix::*;
+
+#[cfg(not(unix))]
+pub use self::other::*;
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        env,
+        ffi::OsString,
+        os::{
+            fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+            unix::net::UnixDatagram,
+        },
+        path::PathBuf,
+        process,
+        time::Duration,
+    };
+
+    use nix::{
+        fcntl::{fcntl, FcntlArg, FdFlag},
+        sys::socket::{
+            getsockname, getsockopt, sockopt, AddressFamily, SockType, SockaddrLike,
+            SockaddrStorage,
+        },
+        time::{clock_gettime, ClockId},
+    };
+    use tracing::{debug, warn};
+
+    use crate::cli::listen::Listener;
+
+    /// The first fd passed by systemd.
+    const LISTEN_FDS_START: RawFd = 3;
+
+    /// Take the sockets passed by systemd socket activation, if any.
+    ///
+    /// The `LISTEN_*` variables are removed from the environment so they aren't inherited by
+    /// anything else. This must be called before any other threads are started.
+    pub fn listen_fds() -> Result<Vec<OwnedFd>, String> {
+        let pid = env::var("LISTEN_PID").ok();
+        let fds = env::var("LISTEN_FDS").ok();
+
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+
+        let count = parse_listen_fds(pid.as_deref(), fds.as_deref(), process::id())?;
+
+        (LISTEN_FDS_START..LISTEN_FDS_START + count)
+            .map(|fd| {
+                // Safety: systemd passes us ownership of these fds, and each is only taken once
+                let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+                fcntl(&fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).map_err(|e| {
+                    format!("Error setting up fd {} from systemd: {e}", fd.as_raw_fd())
+                })?;
+                Ok(fd)
+            })
+            .collect()
+    }
+
+    /// Parse the number of fds passed to `pid`, or 0 if they were meant for another process.
+    pub(in crate::cli) fn parse_listen_fds(
+        pid: Option<&str>,
+        fds: Option<&str>,
+        this_pid: u32,
+    ) -> Result<RawFd, String> {
+        let (Some(pid), Some(fds)) = (pid, fds) else {
+            return Ok(0);
+        };
+
+        if pid.parse::<u32>().ok() != Some(this_pid) {
+            return Ok(0);
+        }
+
+        match fds.parse::<RawFd>() {
+            Ok(fds) if (0..=RawFd::MAX - LISTEN_FDS_START).contains(&fds) => Ok(fds),
+            _ => Err(format!("Invalid LISTEN_FDS from systemd: {fds}")),
+        }
+    }
+
+    /// Create a listener from a socket passed by systemd.
+    ///
+    /// The socket must be a listening TCP or Unix domain stream socket, e.g. from `ListenStream=`.
+    pub fn listener(fd: OwnedFd) -> Result<Listener, String> {
+        let raw = fd.as_raw_fd();
+        let error = |e: &dyn std::fmt::Display| format!("Error using fd {raw} from systemd: {e}");
+
+        let is_listening = getsockopt(&fd, sockopt::AcceptConn).map_err(|e| error(&e))?;
+        let sock_type = getsockopt(&fd, sockopt::SockType).map_err(|e| error(&e))?;
+        if !is_listening || sock_type != SockType::Stream {
+            return Err(error(&"not a listening stream socket"));
+        }
+
+        let family = getsockname::<SockaddrStorage>(raw)
+            .map_err(|e| error(&e))?
+            .family();
+
+        match family {
+            Some(AddressFamily::Inet | AddressFamily::Inet6) => {
+                Listener::from_std_tcp(std::net::TcpListener::from(fd)).map_err(|e| error(&e))
+            }
+            Some(AddressFamily::Unix) => {
+                Listener::from_std_unix(std::os::unix::net::UnixListener::from(fd))
+                    .map_err(|e| error(&e))
+            }
+            _ => Err(error(&"unsupported address family")),
+        }
+    }
+
+    /// Sends notifications to systemd over `NOTIFY_SOCKET`, if set.
+    pub struct Notifier {
+        socket: Option<(UnixDatagram, NotifyAddr)>,
+    }
+
+    enum NotifyAddr {
+        Path(PathBuf),
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Abstract(Vec<u8>),
+    }
+
+    impl Notifier {
+        pub fn from_env() -> Self {
+            Self::new(env::var_os("NOTIFY_SOCKET"))
+        }
+
+        /// Create a notifier for the given `NOTIFY_SOCKET` path.
+        pub(in crate::cli) fn new(path: Option<OsString>) -> Self {
+            let socket = path.and_then(|path| {
+                let addr = notify_addr(path.into())?;
+                match UnixDatagram::unbound() {
+                    Ok(socket) => Some((socket, addr)),
+                    Err(e) => {
+                        warn!("Error creating socket to notify systemd: {e}");
+                        None
+                    }
+                }
+            });
+
+            Self { socket }
+        }
+
+        /// Tell systemd that startup (or reloading) has finished.
+        pub fn ready(&self) {
+            self.notify("READY=1");
+        }
+
+        /// Tell systemd that the config is being reloaded. [`Notifier::ready`] must be called once
+        /// reloading has finished.
+        pub fn reloading(&self) {
+            match clock_gettime(ClockId::CLOCK_MONOTONIC) {
+                Ok(now) => self.notify(&format!(
+                    "RELOADING=1\nMONOTONIC_USEC={}",
+                    Duration::from(now).as_micros()
+                )),
+                Err(e) => warn!("Error getting time to notify systemd: {e}"),
+            }
+        }
+
+        /// Tell systemd that we're shutting down.
+        pub fn stopping(&self) {
+            self.notify("STOPPING=1");
+        }
+
+        /// Tell systemd that we're still alive.
+        pub fn watchdog(&self) {
+            self.notify("WATCHDOG=1");
+        }
+
+        /// How often to call [`Notifier::watchdog`], if the watchdog is enabled for this process.
+        pub fn watchdog_interval(&self) -> Option<Duration> {
+            self.socket.as_ref()?;
+
+            parse_watchdog(
+                env::var("WATCHDOG_USEC").ok().as_deref(),
+                env::var("WATCHDOG_PID").ok().as_deref(),
+                process::id(),
+            )
+        }
+
+        fn notify(&self, state: &str) {
+            let Some((socket, addr)) = &self.socket else {
+                return;
+            };
+
+            debug!("Notifying systemd: {}", state.replace('\n', " "));
+
+            let result = match addr {
+                NotifyAddr::Path(path) => socket.send_to(state.as_bytes(), path),
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                NotifyAddr::Abstract(name) => {
+                    #[cfg(target_os = "android")]
+                    use std::os::android::net::SocketAddrExt;
+                    #[cfg(target_os = "linux")]
+                    use std::os::linux::net::SocketAddrExt;
+
+                    std::os::unix::net::SocketAddr::from_abstract_name(name)
+                        .and_then(|addr| socket.send_to_addr(state.as_bytes(), &addr))
+                }
+            };
+
+            if let Err(e) = result {
+                warn!("Error notifying systemd: {e}");
+            }
+        }
+    }
+
+    fn notify_addr(path: PathBuf) -> Option<NotifyAddr> {
+        use std::os::unix::ffi::OsStrExt;
+
+        match path.as_os_str().as_bytes() {
+            [] => None,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            [b'@', name @ ..] => Some(NotifyAddr::Abstract(name.to_vec())),
+            [b'/', ..] => Some(NotifyAddr::Path(path)),
+            _ => {
+                warn!("Unsupported NOTIFY_SOCKET: {}", path.display());
+                None
+            }
+        }
+    }
+
+    /// Parse how often to notify the watchdog, which is half of the timeout systemd gave us.
+    pub(in crate::cli) fn parse_watchdog(
+        usec: Option<&str>,
+        pid: Option<&str>,
+        this_pid: u32,
+    ) -> Option<Duration> {
+        if let Some(pid) = pid {
+            if pid.parse::<u32>().ok() != Some(this_pid) {
+                return None;
+            }
+        }
+
+        match usec?.parse::<u64>() {
+            Ok(0) | Err(_) => None,
+            Ok(usec) => Some(Duration::from_micros(usec) / 2),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod other {
+    use std::time::Duration;
+
+    /// The fd type systemd would pass, which doesn't exist on this platform.
+    pub enum OwnedFd {}
+
+    pub fn listen_fds() -> Result<Vec<OwnedFd>, String> {
+        Ok(Vec::new())
+    }
+
+    pub fn listener(fd: OwnedFd) -> Result<crate::cli::listen::Listener, String> {
+        match fd {}
+    }
+
+    pub struct Notifier;
+
+    impl Notifier {
+        pub fn from_env() -> Self {
+            Self
+        }
+
+        pub fn ready(&self) {}
+
+        pub fn reloading(&self) {}
+
+        pub fn stopping(&self) {}
+
+        pub fn watchdog(&self) {}
+
+        pub fn watchdog_interval(&self) -> Option<Duration> {
+            None
+        }
+    }
+}
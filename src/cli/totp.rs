@@ -0,0 +1,45 @@
+use std::{fs::File, io::Write, path::PathBuf};
+
+use clap::Args;
+use dumb_auth::TotpSecret;
+
+use super::common::fatal;
+
+/// Generate a TOTP secret for use with `--totp-secret[-file]`.
+///
+/// The secret is written to stdout or the output file, and an `otpauth://` URI for adding it to an
+/// authenticator app is written to stderr.
+#[derive(Args, Debug, PartialEq)]
+pub struct TotpArgs {
+    /// File to write the secret to instead of stdout (file will be overwritten).
+    pub output: Option<PathBuf>,
+    /// Issuer shown in authenticator apps.
+    #[arg(long, default_value = "dumb-auth")]
+    pub issuer: String,
+    /// Account name shown in authenticator apps.
+    #[arg(long, default_value = "dumb-auth")]
+    pub account: String,
+}
+
+pub fn totp(args: TotpArgs) {
+    let secret = TotpSecret::generate();
+
+    match args.output {
+        None => println!("{}", secret.to_base32()),
+        Some(path) => {
+            let mut options = File::options();
+            options.write(true).create(true).truncate(true);
+
+            // Only readable by the owner, as unlike a password hash, the secret can be used as is
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+            options
+                .open(path)
+                .and_then(|mut f| writeln!(f, "{}", secret.to_base32()))
+                .unwrap_or_else(|e| fatal("writing to output file", e))
+        }
+    }
+
+    eprintln!("{}", secret.to_uri(&args.issuer, &args.account));
+}
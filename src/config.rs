@@ -1,22 +1,43 @@
 use std::{fmt, str::FromStr};
 
+use axum::http::HeaderName;
 use duration_str::HumanFormat;
 use password_hash::PasswordHashString;
 use time::Duration;
 
+use crate::{
+    ratelimit::RateLimitConfig, sessions::SessionId, signed_sessions::SessionKeys, totp::TotpSecret,
+};
+
 #[derive(Clone, Debug)]
 pub struct AppConfig {
     pub public_path: String,
     pub auth_config: AuthConfig,
+    /// How often to delete expired sessions from the datastore, or `None` to never delete them.
+    pub session_sweep_interval: Option<Duration>,
+    /// Limit failed password attempts per client, or `None` to not limit them.
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Header containing the client's IP address, set by a trusted reverse proxy. If `None`, the
+    /// address of the connecting peer is used.
+    pub client_ip_header: Option<HeaderName>,
+    /// Path prefix Envoy's `ext_authz` filter is configured with. The rest of the path is the
+    /// original request URI.
+    pub ext_authz_prefix: String,
 }
 
 impl AppConfig {
     pub const DEFAULT_PUBLIC_PATH: &str = "/auth";
+    pub const DEFAULT_SESSION_SWEEP_INTERVAL: Duration = Duration::hours(1);
+    pub const DEFAULT_EXT_AUTHZ_PREFIX: &str = "/ext_authz";
 
     pub fn default(auth_config: AuthConfig) -> Self {
         Self {
             public_path: Self::DEFAULT_PUBLIC_PATH.into(),
             auth_config,
+            session_sweep_interval: Some(Self::DEFAULT_SESSION_SWEEP_INTERVAL),
+            rate_limit: None,
+            client_ip_header: None,
+            ext_authz_prefix: Self::DEFAULT_EXT_AUTHZ_PREFIX.into(),
         }
     }
 }
@@ -24,12 +45,26 @@ impl AppConfig {
 #[derive(Clone, Debug)]
 pub struct AuthConfig {
     pub password: Password,
+    /// Require a TOTP code in addition to the password when logging in interactively.
+    pub totp_secret: Option<TotpSecret>,
     pub allow_basic: bool,
     pub allow_bearer: bool,
     pub allow_session: bool,
+    /// Add headers identifying how the request was authenticated to valid responses, or `None` to
+    /// not add them.
+    pub identity_headers: Option<IdentityHeaders>,
     pub session_cookie_name: String,
     pub session_cookie_domain: Option<String>,
+    /// How long sessions last after logging in, or the maximum lifetime if `session_idle_timeout`
+    /// is set.
     pub session_expiry: SessionExpiry,
+    /// Expire sessions which haven't been used for this long. Sessions are renewed when used.
+    pub session_idle_timeout: Option<Duration>,
+    /// Keys to sign stateless session cookies with, instead of storing sessions in the datastore.
+    pub session_keys: Option<SessionKeys>,
+    /// Signed sessions to reject before they expire, since they can't be deleted.
+    pub revoked_sessions: Vec<SessionId>,
+    pub allowed_redirect_hosts: Vec<String>,
 }
 
 impl AuthConfig {
@@ -39,12 +74,45 @@ impl AuthConfig {
     pub fn default(password: Password) -> Self {
         Self {
             password,
+            totp_secret: None,
             allow_basic: false,
             allow_bearer: false,
             allow_session: true,
+            identity_headers: None,
             session_cookie_name: Self::DEFAULT_SESSION_COOKIE_NAME.to_string(),
             session_cookie_domain: None,
             session_expiry: Self::DEFAULT_SESSION_EXPIRY,
+            session_idle_timeout: None,
+            session_keys: None,
+            revoked_sessions: Vec::new(),
+            allowed_redirect_hosts: Vec::new(),
+        }
+    }
+}
+
+/// Names of the headers identifying how a request was authenticated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdentityHeaders {
+    /// Set to `basic`, `bearer` or `session`.
+    pub method: HeaderName,
+    /// Set to the ID of the session used to authenticate.
+    pub session_id: HeaderName,
+    /// Set to the (unchecked) username given with HTTP Basic authentication.
+    pub user: HeaderName,
+}
+
+impl IdentityHeaders {
+    pub const DEFAULT_METHOD: HeaderName = HeaderName::from_static("x-auth-method");
+    pub const DEFAULT_SESSION_ID: HeaderName = HeaderName::from_static("x-auth-session-id");
+    pub const DEFAULT_USER: HeaderName = HeaderName::from_static("x-auth-user");
+}
+
+impl Default for IdentityHeaders {
+    fn default() -> Self {
+        Self {
+            method: Self::DEFAULT_METHOD,
+            session_id: Self::DEFAULT_SESSION_ID,
+            user: Self::DEFAULT_USER,
         }
     }
 }
@@ -0,0 +1,152 @@
+//! Checks that a [`SessionStore`] behaves the way the app expects.
+
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use tokio::task::JoinSet;
+
+use crate::{
+    datastore::Datastore,
+    sessions::{LoginMethod, SessionData, SessionId, SessionSecret},
+};
+
+#[cfg(doc)]
+use crate::datastore::SessionStore;
+
+/// Check a datastore behaves as the app expects, panicking if it doesn't.
+///
+/// Meant to be called from the tests of a custom [`SessionStore`], which must be empty to start
+/// with. Times are only checked to the second, so stores may truncate them when storing sessions.
+pub async fn check_datastore_conformance(datastore: impl Into<Datastore>) {
+    let datastore = Arc::new(datastore.into());
+
+    assert_eq!(count(&datastore).await, 0, "datastore should start empty");
+
+    let data = session_data(1);
+    let id = create(&datastore, data.clone()).await;
+    let stored = read(&datastore, id)
+        .await
+        .expect("created session should be readable");
+    assert_eq!(stored.info(id), data.info(id), "read session should match");
+    assert_eq!(
+        stored.secret_hash.as_bytes(),
+        data.secret_hash.as_bytes(),
+        "read session secret hash should match"
+    );
+
+    let missing = SessionId(id.0.wrapping_add(1000));
+    assert!(
+        read(&datastore, missing).await.is_none(),
+        "missing session should not be found"
+    );
+
+    let last_used = data.last_used + Duration::from_secs(60);
+    assert!(
+        datastore.touch_session(id, last_used).await.unwrap(),
+        "touching a session should return true"
+    );
+    assert_eq!(
+        read(&datastore, id).await.unwrap().last_used,
+        last_used,
+        "touching a session should update last_used"
+    );
+    assert!(
+        !datastore.touch_session(missing, last_used).await.unwrap(),
+        "touching a missing session should return false"
+    );
+
+    let mut ids = vec![id];
+    for seconds in 2..=3 {
+        ids.push(create(&datastore, session_data(seconds)).await);
+    }
+    assert_eq!(
+        count(&datastore).await,
+        3,
+        "count should include all sessions"
+    );
+
+    let listed = datastore.list_sessions().await.unwrap();
+    let mut sorted = ids.iter().map(|id| id.0).collect::<Vec<_>>();
+    sorted.sort();
+    assert_eq!(
+        listed
+            .iter()
+            .map(|session| session.id.0)
+            .collect::<Vec<_>>(),
+        sorted,
+        "list should have all sessions in order of ID"
+    );
+
+    assert!(
+        datastore.delete_session(ids[1]).await.unwrap(),
+        "deleting a session should return true"
+    );
+    assert!(
+        !datastore.delete_session(ids[1]).await.unwrap(),
+        "deleting a missing session should return false"
+    );
+    assert!(
+        read(&datastore, ids[1]).await.is_none(),
+        "deleted session should not be found"
+    );
+
+    let new_id = create(&datastore, session_data(4)).await;
+    assert!(!ids.contains(&new_id), "session IDs should not be reused");
+    ids.push(new_id);
+
+    let cutoff = UNIX_EPOCH + Duration::from_secs(3);
+    let deleted = datastore
+        .delete_sessions(Box::new(move |_, data| data.created < cutoff))
+        .await
+        .unwrap();
+    assert_eq!(
+        deleted, 1,
+        "delete_sessions should delete matching sessions"
+    );
+    assert!(read(&datastore, ids[0]).await.is_none());
+    assert!(read(&datastore, ids[2]).await.is_some());
+    assert!(read(&datastore, ids[3]).await.is_some());
+
+    let mut tasks = JoinSet::new();
+    for seconds in 10..20 {
+        let datastore = datastore.clone();
+        tasks.spawn(async move { create(&datastore, session_data(seconds)).await });
+    }
+    let mut concurrent_ids = tasks.join_all().await;
+    concurrent_ids.sort_by_key(|id| id.0);
+    concurrent_ids.dedup();
+    assert_eq!(
+        concurrent_ids.len(),
+        10,
+        "concurrently created sessions should have unique IDs"
+    );
+    assert_eq!(count(&datastore).await, 12);
+}
+
+/// Session data created `seconds` after the epoch.
+fn session_data(seconds: u64) -> SessionData {
+    let created = UNIX_EPOCH + Duration::from_secs(seconds);
+    SessionData {
+        secret_hash: SessionSecret::generate().hash(),
+        created,
+        client_ip: Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, seconds as u8))),
+        user_agent: Some(format!("conformance/{seconds}")),
+        last_used: created,
+        login_method: Some(LoginMethod::Password),
+    }
+}
+
+async fn create(datastore: &Datastore, data: SessionData) -> SessionId {
+    datastore.create_session(data).await.unwrap()
+}
+
+async fn read(datastore: &Datastore, id: SessionId) -> Option<SessionData> {
+    datastore.read_session(id).await.unwrap()
+}
+
+async fn count(datastore: &Datastore) -> u64 {
+    datastore.count_sessions().await.unwrap()
+}
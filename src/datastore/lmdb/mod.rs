@@ -1,31 +1,33 @@
-use std::{fs::File, panic, path::Path};
+use std::{
+    fs::{self, File},
+    path::Path,
+    time::SystemTime,
+};
 
-use heed::{EnvFlags, EnvOpenOptions};
-use tokio::task;
+use heed::{Env, EnvClosingEvent, EnvFlags, EnvOpenOptions, MdbError};
+use tracing::info;
 
 use crate::{
-    datastore::Result,
-    sessions::{SessionData, SessionId},
+    datastore::{
+        reader::{ReadBackend, Reader},
+        writer::{WriteBackend, Writer},
+        DatastoreError, DatastoreOptions, MigrationReport, Result, SessionFilter, SessionStore,
+    },
+    sessions::{SessionData, SessionId, SessionInfo},
 };
 
-pub use self::{reader::ReadMode, writer::WriteMode};
-use self::{reader::Reader, schema::Schema, writer::Writer};
+use self::schema::Schema;
 
-mod reader;
 mod schema;
-mod writer;
 
 pub struct LmdbDatastore {
-    reader: Reader,
-    writer: Writer,
+    reader: Reader<Schema>,
+    writer: Writer<Schema>,
+    closing_event: EnvClosingEvent,
 }
 
 impl LmdbDatastore {
-    pub fn open(path: &Path) -> Result<Self> {
-        Self::open_with(path, ReadMode::default(), WriteMode::default())
-    }
-
-    pub fn open_with(path: &Path, read_mode: ReadMode, write_mode: WriteMode) -> Result<Self> {
+    pub fn open_with(path: &Path, options: DatastoreOptions) -> Result<Self> {
         let is_new = match File::options()
             .create(true)
             .write(true)
@@ -36,41 +38,174 @@ impl LmdbDatastore {
             Err(e) => return Err(heed::Error::Io(e).into()),
         };
 
-        let env = unsafe {
-            EnvOpenOptions::new()
-                .max_dbs(Schema::NUM_DBS)
-                .map_size(4 * 1024 * 1024)
-                .flags(EnvFlags::NO_SUB_DIR)
-                .open(path)?
-        };
+        let max_size = Schema::align_map_size(options.max_size);
+        let env = open_env(path, max_size)?;
+
+        // Doesn't close anything yet, just lets us wait for the last reference to be dropped
+        let closing_event = env.clone().prepare_for_closing();
 
         let schema = if is_new {
-            Schema::init(env)
+            Schema::init(env, max_size)?
         } else {
-            Schema::check(env)
-        }?;
+            let schema = Schema::check(env, max_size)?;
+            if options.migrate {
+                let report = migrate(&schema, false)?;
+                if let Some(backup) = &report.backup {
+                    info!(
+                        "Migrated datastore from version {} to {}, backup saved to {}",
+                        report.from_version,
+                        report.to_version,
+                        backup.display()
+                    );
+                }
+            } else {
+                check_version(&schema)?;
+            }
+            schema
+        };
 
         Ok(Self {
-            reader: Reader::new(schema.clone(), read_mode),
-            writer: Writer::new(schema, write_mode),
+            reader: Reader::new(schema.clone(), options.read_mode),
+            writer: Writer::new(schema, options.write_mode),
+            closing_event,
         })
     }
 
-    pub async fn create_session(&self, data: SessionData) -> Result<SessionId> {
+    /// Migrate an existing datastore to the current version, see [`crate::Datastore::migrate`].
+    pub fn migrate(path: &Path, dry_run: bool) -> Result<MigrationReport> {
+        // Opening would otherwise create a new file
+        fs::metadata(path).map_err(heed::Error::Io)?;
+
+        let max_size = Schema::align_map_size(DatastoreOptions::DEFAULT_MAX_SIZE);
+        let schema = Schema::check(open_env(path, max_size)?, max_size)?;
+        migrate(&schema, dry_run)
+    }
+
+    pub fn closing_event(&self) -> EnvClosingEvent {
+        self.closing_event.clone()
+    }
+}
+
+impl SessionStore for LmdbDatastore {
+    async fn ping(&self) -> Result<()> {
+        self.reader.ping().await
+    }
+
+    async fn create_session(&self, data: SessionData) -> Result<SessionId> {
         self.writer.create_session(data).await
     }
 
-    pub async fn read_session(&self, id: SessionId) -> Result<Option<SessionData>> {
+    async fn read_session(&self, id: SessionId) -> Result<Option<SessionData>> {
         self.reader.read_session(id).await
     }
 
-    pub async fn delete_session(&self, id: SessionId) -> Result<bool> {
+    async fn touch_session(&self, id: SessionId, last_used: SystemTime) -> Result<bool> {
+        self.writer.touch_session(id, last_used).await
+    }
+
+    async fn count_sessions(&self) -> Result<u64> {
+        self.reader.count_sessions().await
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<SessionInfo>> {
+        self.reader.list_sessions().await
+    }
+
+    async fn delete_session(&self, id: SessionId) -> Result<bool> {
         self.writer.delete_session(id).await
     }
+
+    async fn delete_sessions(&self, filter: SessionFilter) -> Result<u64> {
+        self.writer.delete_sessions(filter).await
+    }
 }
 
-async fn do_async<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
-    task::spawn_blocking(f)
-        .await
-        .unwrap_or_else(|e| panic::resume_unwind(e.into_panic()))
+impl ReadBackend for Schema {
+    fn ping(&self) -> Result<()> {
+        Schema::ping(self)
+    }
+
+    fn read_session(&self, id: SessionId) -> Result<Option<SessionData>> {
+        Schema::read_session(self, id)
+    }
+
+    fn count_sessions(&self) -> Result<u64> {
+        Schema::count_sessions(self)
+    }
+
+    fn list_sessions(&self) -> Result<Vec<SessionInfo>> {
+        Schema::list_sessions(self)
+    }
+}
+
+impl WriteBackend for Schema {
+    fn create_session(&self, data: &SessionData) -> Result<SessionId> {
+        grow_if_full(self, |s| s.create_session(data.clone()))
+    }
+
+    fn touch_session(&self, id: SessionId, last_used: SystemTime) -> Result<bool> {
+        grow_if_full(self, |s| s.touch_session(id, last_used))
+    }
+
+    fn delete_session(&self, id: SessionId) -> Result<bool> {
+        grow_if_full(self, |s| s.delete_session(id))
+    }
+
+    fn delete_sessions(&self, filter: &SessionFilter) -> Result<u64> {
+        grow_if_full(self, |s| s.delete_sessions(filter))
+    }
+}
+
+fn open_env(path: &Path, max_size: usize) -> Result<Env> {
+    Ok(unsafe {
+        EnvOpenOptions::new()
+            .max_dbs(Schema::NUM_DBS)
+            .map_size(Schema::INITIAL_MAP_SIZE.min(max_size))
+            .flags(EnvFlags::NO_SUB_DIR)
+            .open(path)?
+    })
+}
+
+/// Fail if the datastore needs migrating.
+fn check_version(schema: &Schema) -> Result<()> {
+    match schema.version()? {
+        Schema::VERSION => Ok(()),
+        from => Err(DatastoreError::NeedsMigration {
+            from,
+            to: Schema::VERSION,
+        }),
+    }
+}
+
+/// Run any migrations needed, backing up the datastore first unless `dry_run`.
+fn migrate(schema: &Schema, dry_run: bool) -> Result<MigrationReport> {
+    let from_version = schema.version()?;
+    if from_version == Schema::VERSION {
+        return Ok(MigrationReport {
+            from_version,
+            to_version: Schema::VERSION,
+            migrations: Vec::new(),
+            backup: None,
+        });
+    }
+
+    let backup = if dry_run {
+        None
+    } else {
+        Some(schema.backup(from_version)?)
+    };
+
+    let report = grow_if_full(schema, |s| s.migrate(dry_run))?;
+    Ok(MigrationReport { backup, ..report })
+}
+
+/// Run a write, growing the map and retrying if the map is full.
+fn grow_if_full<T>(schema: &Schema, mut f: impl FnMut(&Schema) -> Result<T>) -> Result<T> {
+    loop {
+        match f(schema) {
+            Err(DatastoreError::HeedError(heed::Error::Mdb(MdbError::MapFull)))
+                if schema.grow()? => {}
+            result => return result,
+        }
+    }
 }
@@ -1,12 +1,22 @@
+use std::{
+    fs::{self, File},
+    net::IpAddr,
+    path::PathBuf,
+    sync::{Arc, RwLock, RwLockReadGuard},
+    time::SystemTime,
+};
+
 use heed::{
     byteorder::{BigEndian, NativeEndian},
     types::{SerdeBincode, Str, U64},
-    Database, Env,
+    CompactionOption, Database, Env, MdbError, RwTxn,
 };
+use serde::{Deserialize, Serialize};
+use tracing::info;
 
 use crate::{
-    datastore::{DatastoreError, Result},
-    sessions::{SessionData, SessionId},
+    datastore::{DatastoreError, MigrationReport, Result, SessionFilter},
+    sessions::{LoginMethod, SessionData, SessionId, SessionInfo, SessionSecret},
 };
 
 #[derive(Clone)]
@@ -14,19 +24,31 @@ pub struct Schema {
     env: Env,
     default: Database<Str, U64<NativeEndian>>,
     sessions: Database<U64<BigEndian>, SerdeBincode<SessionData>>,
+    max_size: usize,
+    /// Held for reading by every transaction, and for writing while resizing the map, since the
+    /// map can only be resized while no transactions are active.
+    resize_lock: Arc<RwLock<()>>,
 }
 
 impl Schema {
     pub const NUM_DBS: u32 = 2;
+    pub const INITIAL_MAP_SIZE: usize = 4 * 1024 * 1024; // 4 MiB
+    /// Map sizes must be a multiple of the OS page size, 1 MiB is a multiple of any common size.
+    const MAP_SIZE_ALIGN: usize = 1024 * 1024;
+
     const SESSIONS_DB_NAME: &str = "sessions";
 
     const MARKER_KEY: &str = "dumb-auth-datastore";
     const MARKER: u64 = 0x64756d6261757468;
     const VERSION_KEY: &str = "version";
-    const VERSION: u64 = 1;
+    pub const VERSION: u64 = 3;
     const SESSION_ID_COUNTER_KEY: &str = "session-id-counter";
 
-    pub fn init(env: Env) -> Result<Self> {
+    pub fn align_map_size(size: usize) -> usize {
+        (size / Self::MAP_SIZE_ALIGN).max(1) * Self::MAP_SIZE_ALIGN
+    }
+
+    pub fn init(env: Env, max_size: usize) -> Result<Self> {
         let mut wtxn = env.write_txn()?;
 
         // Create DBs
@@ -46,10 +68,12 @@ impl Schema {
             env,
             default,
             sessions,
+            max_size,
+            resize_lock: Default::default(),
         })
     }
 
-    pub fn check(env: Env) -> Result<Self> {
+    pub fn check(env: Env, max_size: usize) -> Result<Self> {
         let rtxn = env.read_txn()?;
 
         let default = env
@@ -63,7 +87,7 @@ impl Schema {
 
         // Check version
         match default.get(&rtxn, Self::VERSION_KEY)? {
-            Some(Self::VERSION) => {}
+            Some(1..=Self::VERSION) => {}
             Some(version) => return Err(DatastoreError::UnknownVersion(version)),
             None => return Err(DatastoreError::Corrupt),
         };
@@ -84,39 +108,639 @@ impl Schema {
             env,
             default,
             sessions,
+            max_size,
+            resize_lock: Default::default(),
+        })
+    }
+
+    pub fn version(&self) -> Result<u64> {
+        self.adopt_if_resized(|| {
+            let _guard = self.txn_guard();
+            let rtxn = self.env.read_txn()?;
+
+            self.default
+                .get(&rtxn, Self::VERSION_KEY)?
+                .ok_or(DatastoreError::Corrupt)
+        })
+    }
+
+    /// Copy the datastore to a new file next to it, named after `version`.
+    pub fn backup(&self, version: u64) -> Result<PathBuf> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut path = self.env.path().as_os_str().to_owned();
+        path.push(format!(".v{}-{}.bak", version, timestamp));
+        let path = PathBuf::from(path);
+
+        // Older versions contain session secrets, so only readable by the owner
+        let mut options = File::options();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&path).map_err(heed::Error::Io)?;
+
+        let result = self.adopt_if_resized(|| {
+            let _guard = self.txn_guard();
+            Ok(self
+                .env
+                .copy_to_file(&mut file, CompactionOption::Disabled)?)
+        });
+        if let Err(e) = result {
+            let _ = fs::remove_file(&path);
+            return Err(e);
+        }
+
+        Ok(path)
+    }
+
+    /// Migrate to the current version in a single write transaction. With `dry_run`, the
+    /// transaction is aborted instead of committed, to check the migrations would succeed.
+    pub fn migrate(&self, dry_run: bool) -> Result<MigrationReport> {
+        self.adopt_if_resized(|| {
+            let _guard = self.txn_guard();
+            let mut wtxn = self.env.write_txn()?;
+
+            // Checked again now no other process can be migrating
+            let from_version = self
+                .default
+                .get(&wtxn, Self::VERSION_KEY)?
+                .ok_or(DatastoreError::Corrupt)?;
+
+            let mut migrations = Vec::new();
+            for migration in MIGRATIONS
+                .iter()
+                .filter(|migration| migration.version >= from_version)
+            {
+                (migration.migrate)(self, &mut wtxn)?;
+                migrations.push(migration.description);
+            }
+
+            if dry_run || migrations.is_empty() {
+                wtxn.abort();
+            } else {
+                self.default
+                    .put(&mut wtxn, Self::VERSION_KEY, &Self::VERSION)?;
+                wtxn.commit()?;
+            }
+
+            Ok(MigrationReport {
+                from_version,
+                to_version: Self::VERSION,
+                migrations,
+                backup: None,
+            })
         })
     }
 
+    /// Add session metadata, with `last_used` set to when the session was created.
+    fn migrate_v1(&self, wtxn: &mut RwTxn) -> Result<()> {
+        self.convert_sessions::<SessionDataV1, SessionDataV2>(wtxn)
+    }
+
+    /// Replace session secrets with their hashes, keeping existing sessions valid.
+    ///
+    /// The old secrets may remain in free pages of the file until they're reused, and in the
+    /// backup made before migrating.
+    fn migrate_v2(&self, wtxn: &mut RwTxn) -> Result<()> {
+        self.convert_sessions::<SessionDataV2, SessionData>(wtxn)
+    }
+
+    fn convert_sessions<From, To>(&self, wtxn: &mut RwTxn) -> Result<()>
+    where
+        From: for<'a> Deserialize<'a> + Into<To> + 'static,
+        To: Serialize + 'static,
+    {
+        let sessions = self
+            .sessions
+            .remap_data_type::<SerdeBincode<From>>()
+            .iter(wtxn)?
+            .collect::<heed::Result<Vec<_>>>()?;
+
+        let converted = self.sessions.remap_data_type::<SerdeBincode<To>>();
+        for (id, data) in sessions {
+            converted.put(wtxn, &id, &data.into())?;
+        }
+
+        Ok(())
+    }
+
+    /// Double the size of the map, up to the max size. Returns `false` if already at max size.
+    pub fn grow(&self) -> Result<bool> {
+        let _guard = self.resize_lock.write().unwrap_or_else(|e| e.into_inner());
+
+        let map_size = self.env.info().map_size;
+        if map_size >= self.max_size {
+            return Ok(false);
+        }
+
+        let new_size = Self::align_map_size(map_size.saturating_mul(2)).min(self.max_size);
+        unsafe { self.env.resize(new_size)? };
+
+        info!("Resized datastore from {} to {} bytes", map_size, new_size);
+        Ok(true)
+    }
+
+    fn txn_guard(&self) -> RwLockReadGuard<'_, ()> {
+        self.resize_lock.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Run `f`, adopting the new map size and retrying if another process has grown the map, e.g.
+    /// `dumb-auth sessions` while the server is running.
+    fn adopt_if_resized<T>(&self, mut f: impl FnMut() -> Result<T>) -> Result<T> {
+        loop {
+            match f() {
+                Err(DatastoreError::HeedError(heed::Error::Mdb(MdbError::MapResized))) => {
+                    let _guard = self.resize_lock.write().unwrap_or_else(|e| e.into_inner());
+                    // Zero uses the size set by the other process
+                    unsafe { self.env.resize(0)? };
+                }
+                result => return result,
+            }
+        }
+    }
+
     pub fn create_session(&self, data: SessionData) -> Result<SessionId> {
-        let mut wtxn = self.env.write_txn()?;
+        self.adopt_if_resized(|| {
+            let _guard = self.txn_guard();
+            let mut wtxn = self.env.write_txn()?;
 
-        // Generate ID
-        let id = self
-            .default
-            .get(&wtxn, Self::SESSION_ID_COUNTER_KEY)?
-            .ok_or(DatastoreError::Corrupt)?;
-        self.default
-            .put(&mut wtxn, Self::SESSION_ID_COUNTER_KEY, &(id + 1))?;
+            // Generate ID
+            let id = self
+                .default
+                .get(&wtxn, Self::SESSION_ID_COUNTER_KEY)?
+                .ok_or(DatastoreError::Corrupt)?;
+            self.default
+                .put(&mut wtxn, Self::SESSION_ID_COUNTER_KEY, &(id + 1))?;
 
-        // Write session
-        self.sessions.put(&mut wtxn, &id, &data)?;
+            // Write session
+            self.sessions.put(&mut wtxn, &id, &data)?;
 
-        wtxn.commit()?;
-        Ok(SessionId(id))
+            wtxn.commit()?;
+            Ok(SessionId(id))
+        })
+    }
+
+    pub fn touch_session(&self, id: SessionId, last_used: SystemTime) -> Result<bool> {
+        self.adopt_if_resized(|| {
+            let _guard = self.txn_guard();
+            let mut wtxn = self.env.write_txn()?;
+
+            let mut data = match self.sessions.get(&wtxn, &id.0)? {
+                Some(data) => data,
+                None => return Ok(false),
+            };
+            data.last_used = last_used;
+            self.sessions.put(&mut wtxn, &id.0, &data)?;
+
+            wtxn.commit()?;
+            Ok(true)
+        })
+    }
+
+    /// Check the datastore can still be read.
+    pub fn ping(&self) -> Result<()> {
+        self.adopt_if_resized(|| {
+            let _guard = self.txn_guard();
+            let rtxn = self.env.read_txn()?;
+
+            match self.default.get(&rtxn, Self::MARKER_KEY)? {
+                Some(Self::MARKER) => Ok(()),
+                _ => Err(DatastoreError::Corrupt),
+            }
+        })
     }
 
     pub fn read_session(&self, id: SessionId) -> Result<Option<SessionData>> {
-        let rtxn = self.env.read_txn()?;
+        self.adopt_if_resized(|| {
+            let _guard = self.txn_guard();
+            let rtxn = self.env.read_txn()?;
 
-        Ok(self.sessions.get(&rtxn, &id.0)?)
+            Ok(self.sessions.get(&rtxn, &id.0)?)
+        })
+    }
+
+    pub fn count_sessions(&self) -> Result<u64> {
+        self.adopt_if_resized(|| {
+            let _guard = self.txn_guard();
+            let rtxn = self.env.read_txn()?;
+
+            Ok(self.sessions.len(&rtxn)?)
+        })
+    }
+
+    pub fn list_sessions(&self) -> Result<Vec<SessionInfo>> {
+        self.adopt_if_resized(|| {
+            let _guard = self.txn_guard();
+            let rtxn = self.env.read_txn()?;
+
+            let mut sessions = Vec::new();
+            for entry in self.sessions.iter(&rtxn)? {
+                let (id, data) = entry?;
+                sessions.push(data.info(SessionId(id)));
+            }
+
+            Ok(sessions)
+        })
     }
 
     pub fn delete_session(&self, id: SessionId) -> Result<bool> {
-        let mut wtxn = self.env.write_txn()?;
+        self.adopt_if_resized(|| {
+            let _guard = self.txn_guard();
+            let mut wtxn = self.env.write_txn()?;
+
+            let deleted = self.sessions.delete(&mut wtxn, &id.0)?;
+
+            wtxn.commit()?;
+            Ok(deleted)
+        })
+    }
+
+    pub fn delete_sessions(&self, filter: &SessionFilter) -> Result<u64> {
+        self.adopt_if_resized(|| {
+            let _guard = self.txn_guard();
+            let mut wtxn = self.env.write_txn()?;
+
+            // Find matching sessions
+            let mut ids = Vec::new();
+            for entry in self.sessions.iter(&wtxn)? {
+                let (id, data) = entry?;
+                if filter(SessionId(id), &data) {
+                    ids.push(id);
+                }
+            }
+
+            // Delete them
+            for id in &ids {
+                self.sessions.delete(&mut wtxn, id)?;
+            }
+
+            wtxn.commit()?;
+            Ok(ids.len() as u64)
+        })
+    }
+}
+
+/// Upgrades a datastore from `version` to `version + 1`.
+struct Migration {
+    version: u64,
+    description: &'static str,
+    migrate: fn(&Schema, &mut RwTxn) -> Result<()>,
+}
+
+/// Migrations from every older version, in order.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Add client IP, user agent, last used time and login method to sessions",
+        migrate: Schema::migrate_v1,
+    },
+    Migration {
+        version: 2,
+        description: "Store hashes of session secrets instead of the secrets",
+        migrate: Schema::migrate_v2,
+    },
+];
+
+/// Session data as stored by version 1.
+#[derive(Clone, Deserialize, Serialize)]
+struct SessionDataV1 {
+    secret: SessionSecret,
+    created: SystemTime,
+}
+
+impl From<SessionDataV1> for SessionDataV2 {
+    fn from(data: SessionDataV1) -> Self {
+        Self {
+            secret: data.secret,
+            created: data.created,
+            client_ip: None,
+            user_agent: None,
+            last_used: data.created,
+            login_method: None,
+        }
+    }
+}
+
+/// Session data as stored by version 2.
+#[derive(Clone, Deserialize, Serialize)]
+struct SessionDataV2 {
+    secret: SessionSecret,
+    created: SystemTime,
+    client_ip: Option<IpAddr>,
+    user_agent: Option<String>,
+    last_used: SystemTime,
+    login_method: Option<LoginMethod>,
+}
+
+impl From<SessionDataV2> for SessionData {
+    fn from(data: SessionDataV2) -> Self {
+        Self {
+            secret_hash: data.secret.hash(),
+            created: data.created,
+            client_ip: data.client_ip,
+            user_agent: data.user_agent,
+            last_used: data.last_used,
+            login_method: data.login_method,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use heed::{types::Bytes, EnvFlags, EnvOpenOptions, MdbError};
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::datastore::{
+        lmdb::{grow_if_full, LmdbDatastore},
+        DatastoreOptions, ReadMode, WriteMode,
+    };
 
-        let deleted = self.sessions.delete(&mut wtxn, &id.0)?;
+    const MIB: usize = 1024 * 1024;
+
+    fn open(dir: &TempDir, max_size: usize) -> Schema {
+        let env = unsafe {
+            EnvOpenOptions::new()
+                .max_dbs(Schema::NUM_DBS)
+                .map_size(Schema::INITIAL_MAP_SIZE.min(max_size))
+                .flags(EnvFlags::NO_SUB_DIR)
+                .open(dir.path().join("dumb-auth.mdb"))
+                .unwrap()
+        };
+
+        Schema::init(env, max_size).unwrap()
+    }
+
+    fn put_blob(schema: &Schema, key: &str, size: usize) -> Result<()> {
+        let _guard = schema.txn_guard();
+        let mut wtxn = schema.env.write_txn()?;
+
+        schema
+            .default
+            .remap_data_type::<Bytes>()
+            .put(&mut wtxn, key, &vec![0; size])?;
 
         wtxn.commit()?;
-        Ok(deleted)
+        Ok(())
+    }
+
+    /// Rewrite a new datastore as version 1, with a single session.
+    fn downgrade_to_v1(schema: &Schema, secret: &SessionSecret, created: SystemTime) {
+        let mut wtxn = schema.env.write_txn().unwrap();
+        schema
+            .default
+            .put(&mut wtxn, Schema::VERSION_KEY, &1)
+            .unwrap();
+        schema
+            .sessions
+            .remap_data_type::<SerdeBincode<SessionDataV1>>()
+            .put(
+                &mut wtxn,
+                &7,
+                &SessionDataV1 {
+                    secret: secret.clone(),
+                    created,
+                },
+            )
+            .unwrap();
+        wtxn.commit().unwrap();
+    }
+
+    #[test]
+    fn migrations_cover_every_version() {
+        let versions = MIGRATIONS.iter().map(|m| m.version).collect::<Vec<_>>();
+        assert_eq!(versions, (1..Schema::VERSION).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn migrates_from_v1() {
+        let dir = TempDir::new().unwrap();
+        let schema = open(&dir, 16 * MIB);
+
+        let secret = SessionSecret::generate();
+        let created = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        downgrade_to_v1(&schema, &secret, created);
+
+        let report = schema.migrate(false).unwrap();
+        assert_eq!(report.from_version, 1);
+        assert_eq!(report.to_version, Schema::VERSION);
+        assert_eq!(report.migrations.len(), 2);
+        assert_eq!(schema.version().unwrap(), Schema::VERSION);
+
+        let data = schema.read_session(SessionId(7)).unwrap().unwrap();
+        assert!(secret.verify(&data.secret_hash));
+        assert_eq!(data.created, created);
+        assert_eq!(data.last_used, created);
+        assert_eq!(data.client_ip, None);
+        assert_eq!(data.user_agent, None);
+        assert_eq!(data.login_method, None);
+
+        // Nothing left to do
+        assert!(schema.migrate(false).unwrap().migrations.is_empty());
+    }
+
+    #[test]
+    fn migrates_from_v2() {
+        let dir = TempDir::new().unwrap();
+        let schema = open(&dir, 16 * MIB);
+
+        let secret = SessionSecret::generate();
+        let created = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let last_used = created + Duration::from_secs(60);
+        let mut wtxn = schema.env.write_txn().unwrap();
+        schema
+            .default
+            .put(&mut wtxn, Schema::VERSION_KEY, &2)
+            .unwrap();
+        schema
+            .sessions
+            .remap_data_type::<SerdeBincode<SessionDataV2>>()
+            .put(
+                &mut wtxn,
+                &7,
+                &SessionDataV2 {
+                    secret: secret.clone(),
+                    created,
+                    client_ip: Some(IpAddr::from([192, 0, 2, 1])),
+                    user_agent: Some("curl/8.0".into()),
+                    last_used,
+                    login_method: Some(LoginMethod::PasswordTotp),
+                },
+            )
+            .unwrap();
+        wtxn.commit().unwrap();
+
+        let report = schema.migrate(false).unwrap();
+        assert_eq!(report.from_version, 2);
+        assert_eq!(report.migrations.len(), 1);
+
+        // Existing sessions are kept, without the secret
+        let data = schema.read_session(SessionId(7)).unwrap().unwrap();
+        assert!(secret.verify(&data.secret_hash));
+        assert!(!SessionSecret::generate().verify(&data.secret_hash));
+        assert_eq!(data.created, created);
+        assert_eq!(data.last_used, last_used);
+        assert_eq!(data.client_ip, Some(IpAddr::from([192, 0, 2, 1])));
+        assert_eq!(data.user_agent.as_deref(), Some("curl/8.0"));
+        assert_eq!(data.login_method, Some(LoginMethod::PasswordTotp));
+
+        let stored = schema
+            .sessions
+            .remap_data_type::<Bytes>()
+            .get(&schema.env.read_txn().unwrap(), &7)
+            .unwrap()
+            .unwrap()
+            .to_vec();
+        // The raw secret bytes follow the length prefix
+        let secret = bincode::serialize(&secret).unwrap();
+        let secret = &secret[secret.len() - 32..];
+        assert!(!stored.windows(secret.len()).any(|window| window == secret));
+    }
+
+    #[test]
+    fn dry_run_does_not_migrate() {
+        let dir = TempDir::new().unwrap();
+        let schema = open(&dir, 16 * MIB);
+        downgrade_to_v1(&schema, &SessionSecret::generate(), SystemTime::now());
+
+        let report = schema.migrate(true).unwrap();
+        assert_eq!(report.from_version, 1);
+        assert_eq!(report.migrations.len(), 2);
+
+        assert_eq!(schema.version().unwrap(), 1);
+        assert!(schema
+            .sessions
+            .remap_data_type::<SerdeBincode<SessionDataV1>>()
+            .get(&schema.env.read_txn().unwrap(), &7)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn only_migrates_on_open_if_enabled() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("dumb-auth.mdb");
+        let schema = open(&dir, 16 * MIB);
+        downgrade_to_v1(&schema, &SessionSecret::generate(), SystemTime::now());
+        drop(schema);
+        let backups = || {
+            fs::read_dir(dir.path())
+                .unwrap()
+                .filter(|entry| {
+                    let name = entry.as_ref().unwrap().file_name();
+                    name.to_string_lossy().starts_with("dumb-auth.mdb.v1-")
+                })
+                .count()
+        };
+
+        let options = DatastoreOptions {
+            read_mode: ReadMode::Sync,
+            write_mode: WriteMode::Sync,
+            max_size: 16 * MIB,
+            migrate: false,
+        };
+        assert!(matches!(
+            LmdbDatastore::open_with(&path, options),
+            Err(DatastoreError::NeedsMigration {
+                from: 1,
+                to: Schema::VERSION
+            })
+        ));
+        assert_eq!(backups(), 0);
+
+        let migrate = DatastoreOptions {
+            migrate: true,
+            ..options
+        };
+        drop(LmdbDatastore::open_with(&path, migrate).unwrap());
+        assert_eq!(backups(), 1);
+        LmdbDatastore::open_with(&path, options).unwrap();
+    }
+
+    #[test]
+    fn backs_up_to_new_file() {
+        let dir = TempDir::new().unwrap();
+        let schema = open(&dir, 16 * MIB);
+        schema
+            .create_session(SessionData::from(SessionDataV2::from(SessionDataV1 {
+                secret: SessionSecret::generate(),
+                created: SystemTime::now(),
+            })))
+            .unwrap();
+
+        let backup = schema.backup(Schema::VERSION).unwrap();
+        assert_eq!(backup.parent(), Some(dir.path()));
+        assert!(backup
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with(&format!("dumb-auth.mdb.v{}-", Schema::VERSION)));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&backup).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let env = unsafe {
+            EnvOpenOptions::new()
+                .max_dbs(Schema::NUM_DBS)
+                .flags(EnvFlags::NO_SUB_DIR)
+                .open(&backup)
+                .unwrap()
+        };
+        let backup = Schema::check(env, 16 * MIB).unwrap();
+        assert_eq!(backup.count_sessions().unwrap(), 1);
+    }
+
+    #[test]
+    fn rejects_newer_version() {
+        let dir = TempDir::new().unwrap();
+        let schema = open(&dir, 16 * MIB);
+
+        let mut wtxn = schema.env.write_txn().unwrap();
+        schema
+            .default
+            .put(&mut wtxn, Schema::VERSION_KEY, &(Schema::VERSION + 1))
+            .unwrap();
+        wtxn.commit().unwrap();
+
+        assert!(matches!(
+            Schema::check(schema.env.clone(), 16 * MIB),
+            Err(DatastoreError::UnknownVersion(version)) if version == Schema::VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn grows_map_when_full() {
+        let dir = TempDir::new().unwrap();
+        let schema = open(&dir, 16 * MIB);
+
+        put_blob(&schema, "a", 3 * MIB).unwrap();
+        grow_if_full(&schema, |s| put_blob(s, "b", 3 * MIB)).unwrap();
+
+        assert_eq!(schema.env.info().map_size, 8 * MIB);
+    }
+
+    #[test]
+    fn fails_when_at_max_size() {
+        let dir = TempDir::new().unwrap();
+        let schema = open(&dir, 4 * MIB);
+
+        put_blob(&schema, "a", 3 * MIB).unwrap();
+        let result = grow_if_full(&schema, |s| put_blob(s, "b", 3 * MIB));
+
+        assert!(matches!(
+            result,
+            Err(DatastoreError::HeedError(heed::Error::Mdb(
+                MdbError::MapFull
+            )))
+        ));
+        assert_eq!(schema.env.info().map_size, 4 * MIB);
     }
 }
@@ -1,11 +1,15 @@
 use std::{
     collections::{hash_map::Entry, HashMap},
     sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
 };
 
 use tokio::sync::RwLock;
 
-use crate::sessions::{SessionData, SessionId};
+use crate::{
+    datastore::{Result, SessionFilter, SessionStore},
+    sessions::{SessionData, SessionId, SessionInfo},
+};
 
 pub struct InMemoryDatastore {
     counter: AtomicU64,
@@ -27,8 +31,8 @@ impl Default for InMemoryDatastore {
     }
 }
 
-impl InMemoryDatastore {
-    pub async fn create_session(&self, data: SessionData) -> SessionId {
+impl SessionStore for InMemoryDatastore {
+    async fn create_session(&self, data: SessionData) -> Result<SessionId> {
         let id = SessionId(self.counter.fetch_add(1, Ordering::Relaxed));
 
         match self.sessions.write().await.entry(id) {
@@ -36,14 +40,48 @@ impl InMemoryDatastore {
             Entry::Occupied(_) => panic!("ran out of session IDs, this should never happen"),
         };
 
-        id
+        Ok(id)
+    }
+
+    async fn read_session(&self, id: SessionId) -> Result<Option<SessionData>> {
+        Ok(self.sessions.read().await.get(&id).cloned())
+    }
+
+    async fn touch_session(&self, id: SessionId, last_used: SystemTime) -> Result<bool> {
+        Ok(match self.sessions.write().await.get_mut(&id) {
+            Some(data) => {
+                data.last_used = last_used;
+                true
+            }
+            None => false,
+        })
     }
 
-    pub async fn read_session(&self, id: SessionId) -> Option<SessionData> {
-        self.sessions.read().await.get(&id).cloned()
+    async fn count_sessions(&self) -> Result<u64> {
+        Ok(self.sessions.read().await.len() as u64)
     }
 
-    pub async fn delete_session(&self, id: SessionId) -> bool {
-        self.sessions.write().await.remove(&id).is_some()
+    async fn list_sessions(&self) -> Result<Vec<SessionInfo>> {
+        let mut sessions = self
+            .sessions
+            .read()
+            .await
+            .iter()
+            .map(|(id, data)| data.info(*id))
+            .collect::<Vec<_>>();
+        sessions.sort_by_key(|session| session.id.0);
+        Ok(sessions)
+    }
+
+    async fn delete_session(&self, id: SessionId) -> Result<bool> {
+        Ok(self.sessions.write().await.remove(&id).is_some())
+    }
+
+    async fn delete_sessions(&self, filter: SessionFilter) -> Result<u64> {
+        let mut sessions = self.sessions.write().await;
+
+        let before = sessions.len();
+        sessions.retain(|id, data| !filter(*id, data));
+        Ok((before - sessions.len()) as u64)
     }
 }
@@ -1,78 +1,415 @@
-use std::path::Path;
+use std::{
+    future::Future,
+    panic,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
 
 use thiserror::Error;
+use tokio::{
+    runtime::{Handle, RuntimeFlavor},
+    task,
+};
 
-use crate::sessions::{SessionData, SessionId};
+use crate::{
+    audit::audit,
+    metrics::Metrics,
+    sessions::{SessionData, SessionId, SessionInfo},
+};
 
+pub use self::conformance::check_datastore_conformance;
 use self::lmdb::LmdbDatastore;
-pub use self::lmdb::{ReadMode, WriteMode};
 use self::memory::InMemoryDatastore;
+use self::sqlite::SqliteDatastore;
+pub use self::{reader::ReadMode, writer::WriteMode};
 
+mod conformance;
 mod lmdb;
 mod memory;
+mod reader;
+mod sqlite;
+mod writer;
 
 type Result<T> = std::result::Result<T, DatastoreError>;
 
-pub struct Datastore(DatastoreInner);
+/// Selects sessions to be deleted by [`SessionStore::delete_sessions`].
+pub type SessionFilter = Box<dyn Fn(SessionId, &SessionData) -> bool + Send>;
 
-enum DatastoreInner {
-    InMemory(InMemoryDatastore),
-    Lmdb(LmdbDatastore),
+/// Storage for sessions, which can be implemented to store sessions somewhere other than the
+/// built-in datastores, and used with [`crate::app`] through [`Datastore::new`].
+///
+/// Implementations should pass [`check_datastore_conformance`].
+pub trait SessionStore: Send + Sync + 'static {
+    /// Check the store is usable, for readiness checks.
+    fn ping(&self) -> impl Future<Output = Result<()>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Store a new session, returning its ID. IDs must never be reused, even after the session is
+    /// deleted.
+    fn create_session(&self, data: SessionData) -> impl Future<Output = Result<SessionId>> + Send;
+
+    fn read_session(
+        &self,
+        id: SessionId,
+    ) -> impl Future<Output = Result<Option<SessionData>>> + Send;
+
+    /// Set a session's `last_used` time. Returns `false` if the session doesn't exist.
+    fn touch_session(
+        &self,
+        id: SessionId,
+        last_used: SystemTime,
+    ) -> impl Future<Output = Result<bool>> + Send;
+
+    fn count_sessions(&self) -> impl Future<Output = Result<u64>> + Send;
+
+    /// List all sessions in order of ID.
+    fn list_sessions(&self) -> impl Future<Output = Result<Vec<SessionInfo>>> + Send;
+
+    /// Delete a session. Returns `false` if the session didn't exist.
+    fn delete_session(&self, id: SessionId) -> impl Future<Output = Result<bool>> + Send;
+
+    /// Delete all sessions matching `filter`, returning how many were deleted.
+    fn delete_sessions(&self, filter: SessionFilter) -> impl Future<Output = Result<u64>> + Send;
+}
+
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// Object safe version of [`SessionStore`], so a [`Datastore`] can hold any implementation.
+trait DynSessionStore: Send + Sync {
+    fn ping(&self) -> BoxFuture<'_, ()>;
+    fn create_session(&self, data: SessionData) -> BoxFuture<'_, SessionId>;
+    fn read_session(&self, id: SessionId) -> BoxFuture<'_, Option<SessionData>>;
+    fn touch_session(&self, id: SessionId, last_used: SystemTime) -> BoxFuture<'_, bool>;
+    fn count_sessions(&self) -> BoxFuture<'_, u64>;
+    fn list_sessions(&self) -> BoxFuture<'_, Vec<SessionInfo>>;
+    fn delete_session(&self, id: SessionId) -> BoxFuture<'_, bool>;
+    fn delete_sessions(&self, filter: SessionFilter) -> BoxFuture<'_, u64>;
+}
+
+impl<S: SessionStore> DynSessionStore for S {
+    fn ping(&self) -> BoxFuture<'_, ()> {
+        Box::pin(SessionStore::ping(self))
+    }
+
+    fn create_session(&self, data: SessionData) -> BoxFuture<'_, SessionId> {
+        Box::pin(SessionStore::create_session(self, data))
+    }
+
+    fn read_session(&self, id: SessionId) -> BoxFuture<'_, Option<SessionData>> {
+        Box::pin(SessionStore::read_session(self, id))
+    }
+
+    fn touch_session(&self, id: SessionId, last_used: SystemTime) -> BoxFuture<'_, bool> {
+        Box::pin(SessionStore::touch_session(self, id, last_used))
+    }
+
+    fn count_sessions(&self) -> BoxFuture<'_, u64> {
+        Box::pin(SessionStore::count_sessions(self))
+    }
+
+    fn list_sessions(&self) -> BoxFuture<'_, Vec<SessionInfo>> {
+        Box::pin(SessionStore::list_sessions(self))
+    }
+
+    fn delete_session(&self, id: SessionId) -> BoxFuture<'_, bool> {
+        Box::pin(SessionStore::delete_session(self, id))
+    }
+
+    fn delete_sessions(&self, filter: SessionFilter) -> BoxFuture<'_, u64> {
+        Box::pin(SessionStore::delete_sessions(self, filter))
+    }
+}
+
+/// The session storage used by the app, either one of the built-in datastores or any
+/// [`SessionStore`].
+pub struct Datastore {
+    store: Box<dyn DynSessionStore>,
+    closing_event: ClosingEvent,
+    metrics: Option<Arc<Metrics>>,
 }
 
 impl Datastore {
+    /// Use a custom [`SessionStore`]. Any `SessionStore` can also be converted with `into()`.
+    pub fn new(store: impl SessionStore) -> Self {
+        Self {
+            store: Box::new(store),
+            closing_event: ClosingEvent(None),
+            metrics: None,
+        }
+    }
+
     pub fn new_in_memory() -> Self {
-        Self(DatastoreInner::InMemory(InMemoryDatastore::new()))
+        Self::new(InMemoryDatastore::new())
     }
 
     pub fn open(path: impl AsRef<Path>) -> Result<Self> {
-        Ok(Self(DatastoreInner::Lmdb(LmdbDatastore::open(
-            path.as_ref(),
-        )?)))
+        Self::open_with(path, DatastoreOptions::default())
     }
 
-    pub fn open_with(
-        path: impl AsRef<Path>,
-        read_mode: ReadMode,
-        write_mode: WriteMode,
-    ) -> Result<Self> {
-        Ok(Self(DatastoreInner::Lmdb(LmdbDatastore::open_with(
-            path.as_ref(),
-            read_mode,
-            write_mode,
-        )?)))
+    pub fn open_with(path: impl AsRef<Path>, options: DatastoreOptions) -> Result<Self> {
+        let store = LmdbDatastore::open_with(path.as_ref(), options)?;
+
+        Ok(Self {
+            closing_event: ClosingEvent(Some(ClosingEventInner::Lmdb(store.closing_event()))),
+            ..Self::new(store)
+        })
     }
 
-    pub(crate) async fn create_session(&self, data: SessionData) -> Result<SessionId> {
-        Ok(match &self.0 {
-            DatastoreInner::InMemory(inner) => inner.create_session(data).await,
-            DatastoreInner::Lmdb(inner) => inner.create_session(data).await?,
+    /// Open a SQLite datastore, creating it if it doesn't exist.
+    ///
+    /// [`DatastoreOptions::max_size`] doesn't apply, SQLite datastores grow as needed.
+    pub fn open_sqlite(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_sqlite_with(path, DatastoreOptions::default())
+    }
+
+    pub fn open_sqlite_with(path: impl AsRef<Path>, options: DatastoreOptions) -> Result<Self> {
+        let store = SqliteDatastore::open_with(path.as_ref(), options)?;
+
+        Ok(Self {
+            closing_event: store.closing_event(),
+            ..Self::new(store)
         })
     }
 
+    /// Migrate an existing datastore file to the current version, backing it up first.
+    ///
+    /// Datastores are also migrated automatically when opened, but this can be used to check
+    /// what would happen first with `dry_run`, which doesn't change or back up the datastore.
+    pub fn migrate(path: impl AsRef<Path>, dry_run: bool) -> Result<MigrationReport> {
+        LmdbDatastore::migrate(path.as_ref(), dry_run)
+    }
+
+    /// Migrate an existing SQLite datastore, see [`Datastore::migrate`].
+    pub fn migrate_sqlite(path: impl AsRef<Path>, dry_run: bool) -> Result<MigrationReport> {
+        SqliteDatastore::migrate(path.as_ref(), dry_run)
+    }
+
+    /// Get an event which can be used to wait for the datastore to close once it's been dropped.
+    ///
+    /// Dropping the datastore doesn't immediately close it, e.g. writes already queued with
+    /// [`WriteMode::AsyncThread`] are still finished first.
+    pub fn closing_event(&self) -> ClosingEvent {
+        self.closing_event.clone()
+    }
+
+    /// Record operation timings in `metrics`.
+    pub(crate) fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Check the datastore is usable.
+    pub(crate) async fn ping(&self) -> Result<()> {
+        self.store.ping().await
+    }
+
+    pub(crate) async fn create_session(&self, data: SessionData) -> Result<SessionId> {
+        self.timed("create_session", self.store.create_session(data))
+            .await
+    }
+
     pub(crate) async fn read_session(&self, id: SessionId) -> Result<Option<SessionData>> {
-        Ok(match &self.0 {
-            DatastoreInner::InMemory(inner) => inner.read_session(id).await,
-            DatastoreInner::Lmdb(inner) => inner.read_session(id).await?,
-        })
+        self.timed("read_session", self.store.read_session(id))
+            .await
+    }
+
+    /// Set a session's `last_used` time. Returns `false` if the session doesn't exist.
+    pub(crate) async fn touch_session(&self, id: SessionId, last_used: SystemTime) -> Result<bool> {
+        self.timed("touch_session", self.store.touch_session(id, last_used))
+            .await
+    }
+
+    pub(crate) async fn count_sessions(&self) -> Result<u64> {
+        self.timed("count_sessions", self.store.count_sessions())
+            .await
+    }
+
+    /// List all sessions in order of ID, including expired sessions not yet removed.
+    pub async fn list_sessions(&self) -> Result<Vec<SessionInfo>> {
+        self.timed("list_sessions", self.store.list_sessions())
+            .await
+    }
+
+    /// Revoke a session, e.g. from the command line. Returns `false` if the session didn't exist.
+    pub async fn revoke_session(&self, id: SessionId) -> Result<bool> {
+        let revoked = self.delete_session(id).await?;
+        if revoked {
+            audit!("session_revoked"; session_id = id.0);
+        }
+        Ok(revoked)
+    }
+
+    /// Revoke all sessions matching `filter`, returning how many were revoked.
+    pub async fn revoke_sessions(
+        &self,
+        filter: impl Fn(&SessionInfo) -> bool + Send + 'static,
+    ) -> Result<u64> {
+        let count = self
+            .delete_sessions(Box::new(move |id, data| filter(&data.info(id))))
+            .await?;
+        if count > 0 {
+            audit!("sessions_revoked"; count);
+        }
+        Ok(count)
     }
 
     pub(crate) async fn delete_session(&self, id: SessionId) -> Result<bool> {
-        Ok(match &self.0 {
-            DatastoreInner::InMemory(inner) => inner.delete_session(id).await,
-            DatastoreInner::Lmdb(inner) => inner.delete_session(id).await?,
-        })
+        self.timed("delete_session", self.store.delete_session(id))
+            .await
+    }
+
+    pub(crate) async fn delete_sessions(&self, filter: SessionFilter) -> Result<u64> {
+        self.timed("delete_sessions", self.store.delete_sessions(filter))
+            .await
+    }
+
+    /// Record how long `op` takes in the metrics, including any time waiting for the write thread.
+    async fn timed<T>(&self, operation: &'static str, op: impl Future<Output = T>) -> T {
+        let start = Instant::now();
+        let result = op.await;
+
+        if let Some(metrics) = &self.metrics {
+            metrics.datastore_operation(operation, start);
+        }
+
+        result
+    }
+}
+
+impl<S: SessionStore> From<S> for Datastore {
+    fn from(store: S) -> Self {
+        Self::new(store)
+    }
+}
+
+/// Run blocking datastore work on the current thread, letting the runtime know if it can.
+fn do_sync<T>(f: impl FnOnce() -> T) -> T {
+    if Handle::try_current().is_ok_and(|h| h.runtime_flavor() != RuntimeFlavor::CurrentThread) {
+        task::block_in_place(f)
+    } else {
+        f()
+    }
+}
+
+/// Run blocking datastore work on the blocking thread pool.
+async fn do_async<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|e| panic::resume_unwind(e.into_panic()))
+}
+
+/// Waits for a [`Datastore`] to close, see [`Datastore::closing_event`].
+#[derive(Clone)]
+pub struct ClosingEvent(Option<ClosingEventInner>);
+
+#[derive(Clone)]
+enum ClosingEventInner {
+    Lmdb(heed::EnvClosingEvent),
+    Guard(Arc<(Mutex<bool>, Condvar)>),
+}
+
+impl ClosingEvent {
+    /// Create an event which happens once the returned [`CloseGuard`] is dropped.
+    fn with_guard() -> (Self, CloseGuard) {
+        let closed = Arc::new((Mutex::new(false), Condvar::new()));
+        (
+            Self(Some(ClosingEventInner::Guard(closed.clone()))),
+            CloseGuard(closed),
+        )
+    }
+
+    /// Block until the datastore has closed, or the timeout elapses. Returns `true` if the
+    /// datastore has closed.
+    ///
+    /// The datastore (and anything holding it, e.g. the app) must be dropped first, otherwise this
+    /// will always time out.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        match &self.0 {
+            None => true,
+            Some(ClosingEventInner::Lmdb(event)) => event.wait_timeout(timeout),
+            Some(ClosingEventInner::Guard(closed)) => {
+                let (closed, condvar) = &**closed;
+                let closed = closed.lock().unwrap_or_else(|e| e.into_inner());
+                let (closed, _) = condvar
+                    .wait_timeout_while(closed, timeout, |closed| !*closed)
+                    .unwrap_or_else(|e| e.into_inner());
+                *closed
+            }
+        }
+    }
+}
+
+/// Signals a [`ClosingEvent`] when dropped, held by everything that must be dropped for a
+/// datastore to be closed.
+struct CloseGuard(Arc<(Mutex<bool>, Condvar)>);
+
+impl Drop for CloseGuard {
+    fn drop(&mut self) {
+        let (closed, condvar) = &*self.0;
+        *closed.lock().unwrap_or_else(|e| e.into_inner()) = true;
+        condvar.notify_all();
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DatastoreOptions {
+    pub read_mode: ReadMode,
+    pub write_mode: WriteMode,
+    /// The maximum size of the datastore file in bytes.
+    ///
+    /// The datastore starts small and grows as needed until it reaches this size.
+    pub max_size: usize,
+    /// Migrate an existing datastore to the current version when opening it, backing it up
+    /// first. Otherwise opening an older datastore fails with [`DatastoreError::NeedsMigration`].
+    pub migrate: bool,
+}
+
+impl DatastoreOptions {
+    pub const DEFAULT_MAX_SIZE: usize = 1024 * 1024 * 1024; // 1 GiB
+}
+
+impl Default for DatastoreOptions {
+    fn default() -> Self {
+        Self {
+            read_mode: ReadMode::default(),
+            write_mode: WriteMode::default(),
+            max_size: Self::DEFAULT_MAX_SIZE,
+            migrate: true,
+        }
+    }
+}
+
+/// What [`Datastore::migrate`] did, or would do for a dry run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MigrationReport {
+    pub from_version: u64,
+    pub to_version: u64,
+    /// Description of each migration, in the order they were applied.
+    pub migrations: Vec<&'static str>,
+    /// Copy of the datastore from before migrating, if anything was migrated.
+    pub backup: Option<PathBuf>,
+}
+
 #[derive(Debug, Error)]
 pub enum DatastoreError {
     #[error("{0}")]
     HeedError(#[from] heed::Error),
+    #[error("{0}")]
+    SqliteError(#[from] rusqlite::Error),
+    #[error("{0}")]
+    IoError(#[from] std::io::Error),
+    /// An error from a custom [`SessionStore`].
+    #[error("{0}")]
+    Other(Box<dyn std::error::Error + Send + Sync>),
     #[error("file does not appear to be a dumb-auth datastore")]
     UnrecognizedFormat,
     #[error("unknown datastore version: {0}")]
     UnknownVersion(u64),
+    /// The datastore is an older version and [`DatastoreOptions::migrate`] wasn't set.
+    #[error("datastore is version {from} and needs migrating to version {to}")]
+    NeedsMigration { from: u64, to: u64 },
     #[error("datastore is corrupted")]
     Corrupt,
 }
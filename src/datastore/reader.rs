@@ -0,0 +1,73 @@
+use crate::{
+    datastore::{do_async, Result},
+    sessions::{SessionData, SessionId, SessionInfo},
+};
+
+/// Blocking reads from one of the built-in datastores, run by a [`Reader`].
+pub trait ReadBackend: Clone + Send + Sync + 'static {
+    fn ping(&self) -> Result<()>;
+    fn read_session(&self, id: SessionId) -> Result<Option<SessionData>>;
+    fn count_sessions(&self) -> Result<u64>;
+    fn list_sessions(&self) -> Result<Vec<SessionInfo>>;
+}
+
+pub struct Reader<B> {
+    backend: B,
+    mode: ReadMode,
+}
+
+impl<B: ReadBackend> Reader<B> {
+    pub fn new(backend: B, mode: ReadMode) -> Self {
+        Self { backend, mode }
+    }
+
+    pub async fn ping(&self) -> Result<()> {
+        match self.mode {
+            ReadMode::Sync => self.backend.ping(),
+            ReadMode::Async => {
+                let backend = self.backend.clone();
+                do_async(move || backend.ping()).await
+            }
+        }
+    }
+
+    pub async fn read_session(&self, id: SessionId) -> Result<Option<SessionData>> {
+        match self.mode {
+            ReadMode::Sync => self.backend.read_session(id),
+            ReadMode::Async => {
+                let backend = self.backend.clone();
+                do_async(move || backend.read_session(id)).await
+            }
+        }
+    }
+
+    pub async fn count_sessions(&self) -> Result<u64> {
+        match self.mode {
+            ReadMode::Sync => self.backend.count_sessions(),
+            ReadMode::Async => {
+                let backend = self.backend.clone();
+                do_async(move || backend.count_sessions()).await
+            }
+        }
+    }
+
+    pub async fn list_sessions(&self) -> Result<Vec<SessionInfo>> {
+        match self.mode {
+            ReadMode::Sync => self.backend.list_sessions(),
+            ReadMode::Async => {
+                let backend = self.backend.clone();
+                do_async(move || backend.list_sessions()).await
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReadMode {
+    /// Read on the async runtime's worker thread. This is fastest with LMDB, where reads never
+    /// wait, but SQLite reads can wait for another connection's write, so use `async` with SQLite.
+    #[default]
+    Sync,
+    /// Read on the blocking thread pool.
+    Async,
+}
@@ -0,0 +1,183 @@
+use std::{
+    fs,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use rusqlite::{Connection, OpenFlags};
+use tracing::info;
+
+use crate::{
+    datastore::{
+        reader::Reader,
+        writer::{WriteBackend, Writer},
+        ClosingEvent, DatastoreError, DatastoreOptions, MigrationReport, Result, SessionFilter,
+        SessionStore,
+    },
+    sessions::{SessionData, SessionId, SessionInfo},
+};
+
+use self::{reader::ReadPool, schema::Schema};
+
+mod reader;
+mod schema;
+
+pub struct SqliteDatastore {
+    reader: Reader<ReadPool>,
+    writer: Writer<Schema>,
+    closing_event: ClosingEvent,
+}
+
+impl SqliteDatastore {
+    /// How long to wait for other connections, e.g. `dumb-auth sessions` while the server is
+    /// running, before failing.
+    const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Open the datastore, creating it if it doesn't exist. `max_size` isn't used, SQLite
+    /// datastores grow as needed.
+    pub fn open_with(path: &Path, options: DatastoreOptions) -> Result<Self> {
+        let (closing_event, close_guard) = ClosingEvent::with_guard();
+        let close_guard = Arc::new(close_guard);
+
+        let conn = open_conn(path, false)?;
+        let write_schema = if Schema::is_new(&conn)? {
+            Schema::init(conn, close_guard.clone())?
+        } else {
+            let schema = Schema::check(conn, close_guard.clone())?;
+            if options.migrate {
+                let report = migrate(&schema, false)?;
+                if let Some(backup) = &report.backup {
+                    info!(
+                        "Migrated datastore from version {} to {}, backup saved to {}",
+                        report.from_version,
+                        report.to_version,
+                        backup.display()
+                    );
+                }
+            } else {
+                check_version(&schema)?;
+            }
+            schema
+        };
+
+        Ok(Self {
+            reader: Reader::new(ReadPool::open(path, close_guard)?, options.read_mode),
+            writer: Writer::new(write_schema, options.write_mode),
+            closing_event,
+        })
+    }
+
+    /// Migrate an existing datastore to the current version, see [`crate::Datastore::migrate`].
+    pub fn migrate(path: &Path, dry_run: bool) -> Result<MigrationReport> {
+        // Opening would otherwise create a new file
+        fs::metadata(path)?;
+
+        let (_, close_guard) = ClosingEvent::with_guard();
+        let schema = Schema::check(open_conn(path, false)?, Arc::new(close_guard))?;
+        migrate(&schema, dry_run)
+    }
+
+    pub fn closing_event(&self) -> ClosingEvent {
+        self.closing_event.clone()
+    }
+}
+
+impl SessionStore for SqliteDatastore {
+    async fn ping(&self) -> Result<()> {
+        self.reader.ping().await
+    }
+
+    async fn create_session(&self, data: SessionData) -> Result<SessionId> {
+        self.writer.create_session(data).await
+    }
+
+    async fn read_session(&self, id: SessionId) -> Result<Option<SessionData>> {
+        self.reader.read_session(id).await
+    }
+
+    async fn touch_session(&self, id: SessionId, last_used: SystemTime) -> Result<bool> {
+        self.writer.touch_session(id, last_used).await
+    }
+
+    async fn count_sessions(&self) -> Result<u64> {
+        self.reader.count_sessions().await
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<SessionInfo>> {
+        self.reader.list_sessions().await
+    }
+
+    async fn delete_session(&self, id: SessionId) -> Result<bool> {
+        self.writer.delete_session(id).await
+    }
+
+    async fn delete_sessions(&self, filter: SessionFilter) -> Result<u64> {
+        self.writer.delete_sessions(filter).await
+    }
+}
+
+impl WriteBackend for Schema {
+    fn create_session(&self, data: &SessionData) -> Result<SessionId> {
+        Schema::create_session(self, data)
+    }
+
+    fn touch_session(&self, id: SessionId, last_used: SystemTime) -> Result<bool> {
+        Schema::touch_session(self, id, last_used)
+    }
+
+    fn delete_session(&self, id: SessionId) -> Result<bool> {
+        Schema::delete_session(self, id)
+    }
+
+    fn delete_sessions(&self, filter: &SessionFilter) -> Result<u64> {
+        Schema::delete_sessions(self, filter)
+    }
+}
+
+/// Open a connection, creating the file if it doesn't exist unless `read_only`.
+fn open_conn(path: &Path, read_only: bool) -> Result<Connection> {
+    let flags = if read_only {
+        OpenFlags::SQLITE_OPEN_READ_ONLY
+    } else {
+        OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE
+    };
+
+    // Each connection is only used by one thread at a time
+    let conn = Connection::open_with_flags(path, flags | OpenFlags::SQLITE_OPEN_NO_MUTEX)?;
+    conn.busy_timeout(SqliteDatastore::BUSY_TIMEOUT)?;
+    Ok(conn)
+}
+
+/// Fail if the datastore needs migrating.
+fn check_version(schema: &Schema) -> Result<()> {
+    match schema.version()? {
+        Schema::VERSION => Ok(()),
+        from => Err(DatastoreError::NeedsMigration {
+            from,
+            to: Schema::VERSION,
+        }),
+    }
+}
+
+/// Run any migrations needed, backing up the datastore first unless `dry_run`.
+fn migrate(schema: &Schema, dry_run: bool) -> Result<MigrationReport> {
+    let from_version = schema.version()?;
+    if from_version == Schema::VERSION {
+        return Ok(MigrationReport {
+            from_version,
+            to_version: Schema::VERSION,
+            migrations: Vec::new(),
+            backup: None,
+        });
+    }
+
+    let backup = if dry_run {
+        None
+    } else {
+        Some(schema.backup(from_version)?)
+    };
+
+    let report = schema.migrate(dry_run)?;
+    Ok(MigrationReport { backup, ..report })
+}
@@ -0,0 +1,96 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    datastore::{reader::ReadBackend, CloseGuard, Result},
+    sessions::{SessionData, SessionId, SessionInfo},
+};
+
+use super::{open_conn, schema::Schema};
+
+/// Read-only connections, each used by one read at a time so that reads don't wait for each other.
+/// Cloning shares the connections.
+#[derive(Clone)]
+pub struct ReadPool(Arc<Inner>);
+
+struct Inner {
+    path: PathBuf,
+    idle: Mutex<Vec<Schema>>,
+    close_guard: Arc<CloseGuard>,
+}
+
+impl ReadPool {
+    /// How many idle connections to keep open, more are opened as needed.
+    const MAX_IDLE: usize = 4;
+
+    /// Open the first connection, checking the datastore can be read.
+    pub fn open(path: &Path, close_guard: Arc<CloseGuard>) -> Result<Self> {
+        let schema = Schema::check(open_conn(path, true)?, close_guard.clone())?;
+        Ok(Self(Arc::new(Inner {
+            path: path.to_owned(),
+            idle: Mutex::new(vec![schema]),
+            close_guard,
+        })))
+    }
+
+    /// Run `f` with an idle connection, or a new one if they're all in use.
+    fn read<T>(&self, f: impl FnOnce(&Schema) -> Result<T>) -> Result<T> {
+        let idle = self.0.idle.lock().unwrap_or_else(|e| e.into_inner()).pop();
+        let schema = match idle {
+            Some(schema) => schema,
+            None => Schema::check(open_conn(&self.0.path, true)?, self.0.close_guard.clone())?,
+        };
+
+        let result = f(&schema);
+
+        let mut idle = self.0.idle.lock().unwrap_or_else(|e| e.into_inner());
+        if idle.len() < Self::MAX_IDLE {
+            idle.push(schema);
+        }
+
+        result
+    }
+}
+
+impl ReadBackend for ReadPool {
+    fn ping(&self) -> Result<()> {
+        self.read(|schema| schema.ping())
+    }
+
+    fn read_session(&self, id: SessionId) -> Result<Option<SessionData>> {
+        self.read(|schema| schema.read_session(id))
+    }
+
+    fn count_sessions(&self) -> Result<u64> {
+        self.read(|schema| schema.count_sessions())
+    }
+
+    fn list_sessions(&self) -> Result<Vec<SessionInfo>> {
+        self.read(|schema| schema.list_sessions())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::datastore::ClosingEvent;
+
+    #[test]
+    fn opens_connections_as_needed() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("dumb-auth.db");
+        let close_guard = Arc::new(ClosingEvent::with_guard().1);
+        Schema::init(open_conn(&path, false).unwrap(), close_guard.clone()).unwrap();
+
+        let pool = ReadPool::open(&path, close_guard).unwrap();
+        let count = pool
+            .read(|_| pool.read(|schema| schema.count_sessions()))
+            .unwrap();
+        assert_eq!(count, 0);
+        assert_eq!(pool.0.idle.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,542 @@
+use std::{
+    fs::{self, File},
+    net::IpAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime},
+};
+
+use rusqlite::{params, Connection, ErrorCode, Row, Transaction, TransactionBehavior};
+
+use crate::{
+    datastore::{CloseGuard, DatastoreError, MigrationReport, Result, SessionFilter},
+    sessions::{LoginMethod, SessionData, SessionId, SessionInfo, SessionSecretHash},
+};
+
+/// A connection to the datastore. Cloning shares the connection.
+#[derive(Clone)]
+pub struct Schema {
+    conn: Arc<Mutex<Connection>>,
+    /// Dropped once every connection is closed.
+    _close_guard: Arc<CloseGuard>,
+}
+
+impl Schema {
+    /// Stored in the SQLite header to identify the file as a dumb-auth datastore.
+    const APPLICATION_ID: i32 = 0x64756d62; // "dumb"
+    /// Stored in the SQLite header as the `user_version`.
+    pub const VERSION: u64 = 1;
+
+    const SESSION_COLUMNS: &str =
+        "secret_hash, created, client_ip, user_agent, last_used, login_method";
+
+    /// Whether the file is empty, i.e. a new datastore.
+    pub fn is_new(conn: &Connection) -> Result<bool> {
+        let page_count: u64 = conn
+            .pragma_query_value(None, "page_count", |row| row.get(0))
+            .map_err(unrecognized_if_not_database)?;
+        Ok(page_count == 0)
+    }
+
+    pub fn init(mut conn: Connection, close_guard: Arc<CloseGuard>) -> Result<Self> {
+        // Keeps the default rollback journal, as WAL needs shared memory so doesn't work on network
+        // filesystems
+
+        let tx = conn.transaction()?;
+
+        // Create tables
+        tx.execute_batch(
+            "CREATE TABLE sessions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                secret_hash BLOB NOT NULL,
+                created INTEGER NOT NULL,
+                client_ip TEXT,
+                user_agent TEXT,
+                last_used INTEGER NOT NULL,
+                login_method TEXT
+            ) STRICT;",
+        )?;
+
+        // Create metadata
+        tx.pragma_update(None, "application_id", Self::APPLICATION_ID)?;
+        tx.pragma_update(None, "user_version", Self::VERSION)?;
+
+        tx.commit()?;
+
+        Ok(Self::new(conn, close_guard))
+    }
+
+    pub fn check(conn: Connection, close_guard: Arc<CloseGuard>) -> Result<Self> {
+        // Check marker
+        let application_id: i32 = conn
+            .pragma_query_value(None, "application_id", |row| row.get(0))
+            .map_err(unrecognized_if_not_database)?;
+        if application_id != Self::APPLICATION_ID {
+            return Err(DatastoreError::UnrecognizedFormat);
+        }
+
+        // Check version
+        let version: u64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        match version {
+            1..=Self::VERSION => {}
+            0 => return Err(DatastoreError::Corrupt),
+            version => return Err(DatastoreError::UnknownVersion(version)),
+        };
+
+        // Check tables
+        let has_sessions: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_schema WHERE type = 'table' AND name = 'sessions')",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_sessions {
+            return Err(DatastoreError::Corrupt);
+        }
+
+        Ok(Self::new(conn, close_guard))
+    }
+
+    fn new(conn: Connection, close_guard: Arc<CloseGuard>) -> Self {
+        Self {
+            conn: Arc::new(Mutex::new(conn)),
+            _close_guard: close_guard,
+        }
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn version(&self) -> Result<u64> {
+        Ok(self
+            .conn()
+            .pragma_query_value(None, "user_version", |row| row.get(0))?)
+    }
+
+    /// Copy the datastore to a new file next to it, named after `version`.
+    pub fn backup(&self, version: u64) -> Result<PathBuf> {
+        let conn = self.conn();
+
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut path = conn.path().ok_or(DatastoreError::Corrupt)?.to_owned();
+        path.push_str(&format!(".v{}-{}.bak", version, timestamp));
+
+        // Created empty first so that it's only readable by the owner
+        let mut options = File::options();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(&path)?;
+
+        if let Err(e) = conn.execute("VACUUM INTO ?1", [&path]) {
+            let _ = fs::remove_file(&path);
+            return Err(e.into());
+        }
+
+        Ok(path.into())
+    }
+
+    /// Migrate to the current version in a single transaction. With `dry_run`, the transaction is
+    /// rolled back instead of committed, to check the migrations would succeed.
+    pub fn migrate(&self, dry_run: bool) -> Result<MigrationReport> {
+        let mut conn = self.conn();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        // Checked again now no other process can be migrating
+        let from_version: u64 = tx.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+        let mut migrations = Vec::new();
+        for migration in MIGRATIONS
+            .iter()
+            .filter(|migration| migration.version >= from_version)
+        {
+            (migration.migrate)(&tx)?;
+            migrations.push(migration.description);
+        }
+
+        if dry_run || migrations.is_empty() {
+            tx.rollback()?;
+        } else {
+            tx.pragma_update(None, "user_version", Self::VERSION)?;
+            tx.commit()?;
+        }
+
+        Ok(MigrationReport {
+            from_version,
+            to_version: Self::VERSION,
+            migrations,
+            backup: None,
+        })
+    }
+
+    /// Check the datastore can still be read.
+    pub fn ping(&self) -> Result<()> {
+        let application_id: i32 =
+            self.conn()
+                .pragma_query_value(None, "application_id", |row| row.get(0))?;
+        match application_id {
+            Self::APPLICATION_ID => Ok(()),
+            _ => Err(DatastoreError::Corrupt),
+        }
+    }
+
+    pub fn create_session(&self, data: &SessionData) -> Result<SessionId> {
+        let conn = self.conn();
+
+        conn.execute(
+            &format!(
+                "INSERT INTO sessions ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                Self::SESSION_COLUMNS
+            ),
+            params![
+                data.secret_hash.as_bytes(),
+                to_unix(data.created),
+                data.client_ip.map(|ip| ip.to_string()),
+                data.user_agent,
+                to_unix(data.last_used),
+                data.login_method.map(|method| method.to_string()),
+            ],
+        )?;
+
+        Ok(SessionId(conn.last_insert_rowid() as u64))
+    }
+
+    pub fn touch_session(&self, id: SessionId, last_used: SystemTime) -> Result<bool> {
+        let updated = self.conn().execute(
+            "UPDATE sessions SET last_used = ?1 WHERE id = ?2",
+            params![to_unix(last_used), id.0],
+        )?;
+        Ok(updated > 0)
+    }
+
+    pub fn read_session(&self, id: SessionId) -> Result<Option<SessionData>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare_cached(&format!(
+            "SELECT {} FROM sessions WHERE id = ?1",
+            Self::SESSION_COLUMNS
+        ))?;
+
+        let mut rows = stmt.query([id.0])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(session_data(row, 0)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn count_sessions(&self) -> Result<u64> {
+        Ok(self
+            .conn()
+            .query_row("SELECT COUNT(*) FROM sessions", [], |row| row.get(0))?)
+    }
+
+    pub fn list_sessions(&self) -> Result<Vec<SessionInfo>> {
+        let conn = self.conn();
+        Ok(all_sessions(&conn)?
+            .into_iter()
+            .map(|(id, data)| data.info(id))
+            .collect())
+    }
+
+    pub fn delete_session(&self, id: SessionId) -> Result<bool> {
+        let deleted = self
+            .conn()
+            .execute("DELETE FROM sessions WHERE id = ?1", [id.0])?;
+        Ok(deleted > 0)
+    }
+
+    pub fn delete_sessions(&self, filter: &SessionFilter) -> Result<u64> {
+        let mut conn = self.conn();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        // Find matching sessions
+        let ids = all_sessions(&tx)?
+            .into_iter()
+            .filter(|(id, data)| filter(*id, data))
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+
+        // Delete them
+        {
+            let mut stmt = tx.prepare_cached("DELETE FROM sessions WHERE id = ?1")?;
+            for id in &ids {
+                stmt.execute([id.0])?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(ids.len() as u64)
+    }
+}
+
+/// Upgrades a datastore from `version` to `version + 1`.
+struct Migration {
+    version: u64,
+    description: &'static str,
+    migrate: fn(&Transaction) -> Result<()>,
+}
+
+/// Migrations from every older version, in order.
+const MIGRATIONS: &[Migration] = &[];
+
+/// All sessions in order of ID.
+fn all_sessions(conn: &Connection) -> Result<Vec<(SessionId, SessionData)>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT id, {} FROM sessions ORDER BY id",
+        Schema::SESSION_COLUMNS
+    ))?;
+
+    let mut sessions = Vec::new();
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        sessions.push((SessionId(row.get(0)?), session_data(row, 1)?));
+    }
+
+    Ok(sessions)
+}
+
+/// Read [`Schema::SESSION_COLUMNS`] from `row`, starting at column `start`.
+fn session_data(row: &Row, start: usize) -> Result<SessionData> {
+    let secret_hash: [u8; 32] = row.get(start)?;
+    let client_ip: Option<String> = row.get(start + 2)?;
+    let login_method: Option<String> = row.get(start + 5)?;
+
+    Ok(SessionData {
+        secret_hash: SessionSecretHash::from(secret_hash),
+        created: from_unix(row.get(start + 1)?),
+        client_ip: client_ip
+            .map(|ip| ip.parse::<IpAddr>())
+            .transpose()
+            .map_err(|_| DatastoreError::Corrupt)?,
+        user_agent: row.get(start + 3)?,
+        last_used: from_unix(row.get(start + 4)?),
+        login_method: login_method
+            .map(|method| match method.as_str() {
+                "password" => Ok(LoginMethod::Password),
+                "password+totp" => Ok(LoginMethod::PasswordTotp),
+                _ => Err(DatastoreError::Corrupt),
+            })
+            .transpose()?,
+    })
+}
+
+/// Times are stored as nanoseconds since the Unix epoch, so other tools can read them, e.g. with
+/// `datetime(created / 1000000000, 'unixepoch')`.
+fn to_unix(time: SystemTime) -> i64 {
+    let nanos = |duration: Duration| i64::try_from(duration.as_nanos()).unwrap_or(i64::MAX);
+    match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(since) => nanos(since),
+        Err(e) => -nanos(e.duration()),
+    }
+}
+
+fn from_unix(nanos: i64) -> SystemTime {
+    let duration = Duration::from_nanos(nanos.unsigned_abs());
+    if nanos >= 0 {
+        SystemTime::UNIX_EPOCH + duration
+    } else {
+        SystemTime::UNIX_EPOCH - duration
+    }
+}
+
+/// Files that aren't SQLite databases at all, e.g. an LMDB datastore, aren't recognized either.
+fn unrecognized_if_not_database(e: rusqlite::Error) -> DatastoreError {
+    match e.sqlite_error_code() {
+        Some(ErrorCode::NotADatabase) => DatastoreError::UnrecognizedFormat,
+        _ => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{datastore::ClosingEvent, sessions::SessionSecret};
+
+    fn open(dir: &TempDir) -> Schema {
+        let conn = Connection::open(dir.path().join("dumb-auth.db")).unwrap();
+        assert!(Schema::is_new(&conn).unwrap());
+        Schema::init(conn, close_guard()).unwrap()
+    }
+
+    fn reopen(dir: &TempDir) -> Result<Schema> {
+        let conn = Connection::open(dir.path().join("dumb-auth.db")).unwrap();
+        assert!(!Schema::is_new(&conn).unwrap());
+        Schema::check(conn, close_guard())
+    }
+
+    fn close_guard() -> Arc<CloseGuard> {
+        Arc::new(ClosingEvent::with_guard().1)
+    }
+
+    #[test]
+    fn migrations_cover_every_version() {
+        let versions = MIGRATIONS.iter().map(|m| m.version).collect::<Vec<_>>();
+        assert_eq!(versions, (1..Schema::VERSION).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn stores_sessions_as_columns() {
+        let dir = TempDir::new().unwrap();
+        let schema = open(&dir);
+
+        let secret = SessionSecret::generate();
+        let created = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let id = schema
+            .create_session(&SessionData {
+                secret_hash: secret.hash(),
+                created,
+                client_ip: Some(IpAddr::from([192, 0, 2, 1])),
+                user_agent: Some("curl/8.0".into()),
+                last_used: created,
+                login_method: Some(LoginMethod::PasswordTotp),
+            })
+            .unwrap();
+
+        // Readable with other tools
+        let row: (i64, String, String) = schema
+            .conn()
+            .query_row(
+                "SELECT created, client_ip, login_method FROM sessions WHERE id = ?1",
+                [id.0],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            row,
+            (
+                1_700_000_000_000_000_000,
+                "192.0.2.1".into(),
+                "password+totp".into()
+            )
+        );
+
+        let data = reopen(&dir).unwrap().read_session(id).unwrap().unwrap();
+        assert!(secret.verify(&data.secret_hash));
+        assert_eq!(data.created, created);
+        assert_eq!(data.client_ip, Some(IpAddr::from([192, 0, 2, 1])));
+        assert_eq!(data.login_method, Some(LoginMethod::PasswordTotp));
+    }
+
+    #[test]
+    fn uses_rollback_journal() {
+        let dir = TempDir::new().unwrap();
+        open(&dir);
+
+        let schema = reopen(&dir).unwrap();
+        let journal_mode: String = schema
+            .conn()
+            .pragma_query_value(None, "journal_mode", |row| row.get(0))
+            .unwrap();
+        assert_eq!(journal_mode, "delete");
+    }
+
+    #[test]
+    fn rejects_newer_version() {
+        let dir = TempDir::new().unwrap();
+        let schema = open(&dir);
+        schema
+            .conn()
+            .pragma_update(None, "user_version", Schema::VERSION + 1)
+            .unwrap();
+
+        assert!(matches!(
+            reopen(&dir),
+            Err(DatastoreError::UnknownVersion(version)) if version == Schema::VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn rejects_other_databases() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("dumb-auth.db");
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch("CREATE TABLE other (id INTEGER PRIMARY KEY)")
+            .unwrap();
+
+        assert!(matches!(
+            reopen(&dir),
+            Err(DatastoreError::UnrecognizedFormat)
+        ));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("dumb-auth.db"), [0xff; 4096]).unwrap();
+
+        let conn = Connection::open(dir.path().join("dumb-auth.db")).unwrap();
+        assert!(matches!(
+            Schema::is_new(&conn),
+            Err(DatastoreError::UnrecognizedFormat)
+        ));
+    }
+
+    #[test]
+    fn rejects_corrupt_data() {
+        let dir = TempDir::new().unwrap();
+        let schema = open(&dir);
+        let id = schema
+            .create_session(&SessionData {
+                secret_hash: SessionSecret::generate().hash(),
+                created: UNIX_EPOCH,
+                client_ip: None,
+                user_agent: None,
+                last_used: UNIX_EPOCH,
+                login_method: None,
+            })
+            .unwrap();
+        schema
+            .conn()
+            .execute(
+                "UPDATE sessions SET login_method = 'magic' WHERE id = ?1",
+                [id.0],
+            )
+            .unwrap();
+
+        assert!(matches!(
+            schema.read_session(id),
+            Err(DatastoreError::Corrupt)
+        ));
+    }
+
+    #[test]
+    fn backs_up_to_new_file() {
+        let dir = TempDir::new().unwrap();
+        let schema = open(&dir);
+        schema
+            .create_session(&SessionData {
+                secret_hash: SessionSecret::generate().hash(),
+                created: SystemTime::now(),
+                client_ip: None,
+                user_agent: None,
+                last_used: SystemTime::now(),
+                login_method: None,
+            })
+            .unwrap();
+
+        let backup = schema.backup(Schema::VERSION).unwrap();
+        assert_eq!(backup.parent(), Some(dir.path()));
+        assert!(backup
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with(&format!("dumb-auth.db.v{}-", Schema::VERSION)));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&backup).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let backup = Schema::check(Connection::open(&backup).unwrap(), close_guard()).unwrap();
+        assert_eq!(backup.count_sessions().unwrap(), 1);
+    }
+}
@@ -0,0 +1,135 @@
+use std::{thread, time::SystemTime};
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    datastore::{do_async, do_sync, Result, SessionFilter},
+    sessions::{SessionData, SessionId},
+};
+
+/// Blocking writes to one of the built-in datastores, run by a [`Writer`].
+pub trait WriteBackend: Clone + Send + Sync + 'static {
+    fn create_session(&self, data: &SessionData) -> Result<SessionId>;
+    fn touch_session(&self, id: SessionId, last_used: SystemTime) -> Result<bool>;
+    fn delete_session(&self, id: SessionId) -> Result<bool>;
+    fn delete_sessions(&self, filter: &SessionFilter) -> Result<u64>;
+}
+
+pub struct Writer<B>(Inner<B>);
+
+enum Inner<B> {
+    Sync(B),
+    Async(B),
+    AsyncThread(mpsc::Sender<WriteOp>),
+}
+
+type WriteRet<T> = oneshot::Sender<Result<T>>;
+
+enum WriteOp {
+    CreateSession(SessionData, WriteRet<SessionId>),
+    TouchSession(SessionId, SystemTime, WriteRet<bool>),
+    DeleteSession(SessionId, WriteRet<bool>),
+    DeleteSessions(SessionFilter, WriteRet<u64>),
+}
+
+impl<B: WriteBackend> Writer<B> {
+    pub fn new(backend: B, mode: WriteMode) -> Self {
+        match mode {
+            WriteMode::Sync => Self(Inner::Sync(backend)),
+            WriteMode::Async => Self(Inner::Async(backend)),
+            WriteMode::AsyncThread => {
+                let (tx, rx) = mpsc::channel(1);
+                Self::spawn_write_thread(backend, rx);
+                Self(Inner::AsyncThread(tx))
+            }
+        }
+    }
+
+    fn spawn_write_thread(backend: B, mut rx: mpsc::Receiver<WriteOp>) {
+        thread::spawn(move || {
+            while let Some(op) = rx.blocking_recv() {
+                match op {
+                    WriteOp::CreateSession(data, ret) => {
+                        let _ = ret.send(backend.create_session(&data));
+                    }
+                    WriteOp::TouchSession(id, last_used, ret) => {
+                        let _ = ret.send(backend.touch_session(id, last_used));
+                    }
+                    WriteOp::DeleteSession(id, ret) => {
+                        let _ = ret.send(backend.delete_session(id));
+                    }
+                    WriteOp::DeleteSessions(filter, ret) => {
+                        let _ = ret.send(backend.delete_sessions(&filter));
+                    }
+                }
+            }
+        });
+    }
+
+    pub async fn create_session(&self, data: SessionData) -> Result<SessionId> {
+        match &self.0 {
+            Inner::Sync(backend) => do_sync(|| backend.create_session(&data)),
+            Inner::Async(backend) => {
+                let backend = backend.clone();
+                do_async(move || backend.create_session(&data)).await
+            }
+            Inner::AsyncThread(op_tx) => {
+                do_op(op_tx, |ret| WriteOp::CreateSession(data, ret)).await
+            }
+        }
+    }
+
+    pub async fn touch_session(&self, id: SessionId, last_used: SystemTime) -> Result<bool> {
+        match &self.0 {
+            Inner::Sync(backend) => do_sync(|| backend.touch_session(id, last_used)),
+            Inner::Async(backend) => {
+                let backend = backend.clone();
+                do_async(move || backend.touch_session(id, last_used)).await
+            }
+            Inner::AsyncThread(op_tx) => {
+                do_op(op_tx, |ret| WriteOp::TouchSession(id, last_used, ret)).await
+            }
+        }
+    }
+
+    pub async fn delete_session(&self, id: SessionId) -> Result<bool> {
+        match &self.0 {
+            Inner::Sync(backend) => do_sync(|| backend.delete_session(id)),
+            Inner::Async(backend) => {
+                let backend = backend.clone();
+                do_async(move || backend.delete_session(id)).await
+            }
+            Inner::AsyncThread(op_tx) => do_op(op_tx, |ret| WriteOp::DeleteSession(id, ret)).await,
+        }
+    }
+
+    pub async fn delete_sessions(&self, filter: SessionFilter) -> Result<u64> {
+        match &self.0 {
+            Inner::Sync(backend) => do_sync(|| backend.delete_sessions(&filter)),
+            Inner::Async(backend) => {
+                let backend = backend.clone();
+                do_async(move || backend.delete_sessions(&filter)).await
+            }
+            Inner::AsyncThread(op_tx) => {
+                do_op(op_tx, |ret| WriteOp::DeleteSessions(filter, ret)).await
+            }
+        }
+    }
+}
+
+async fn do_op<T>(
+    op_tx: &mpsc::Sender<WriteOp>,
+    f: impl FnOnce(oneshot::Sender<T>) -> WriteOp,
+) -> T {
+    let (ret_tx, ret_rx) = oneshot::channel();
+    op_tx.send(f(ret_tx)).await.unwrap();
+    ret_rx.await.unwrap()
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum WriteMode {
+    Sync,
+    Async,
+    #[default]
+    AsyncThread,
+}
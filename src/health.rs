@@ -0,0 +1,22 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode};
+use tracing::error;
+
+use crate::datastore::Datastore;
+
+/// Liveness check, always succeeds while the server is able to respond.
+pub async fn handle_health() -> (StatusCode, &'static str) {
+    (StatusCode::OK, "OK")
+}
+
+/// Readiness check, succeeds if the datastore can be used.
+pub async fn handle_ready(State(datastore): State<Arc<Datastore>>) -> (StatusCode, &'static str) {
+    match datastore.ping().await {
+        Ok(()) => (StatusCode::OK, "OK"),
+        Err(e) => {
+            error!("Datastore not ready: {e}");
+            (StatusCode::SERVICE_UNAVAILABLE, "Service Unavailable")
+        }
+    }
+}
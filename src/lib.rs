@@ -1,43 +1,86 @@
-use std::sync::Arc;
+use std::{
+    future::Future,
+    sync::{Arc, RwLock, RwLockReadGuard},
+    time::Duration,
+};
 
 use axum::{
     extract::FromRef,
-    http::StatusCode,
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
-    routing::{any, get},
+    routing::{any, get, post},
     Router,
 };
 use thiserror::Error;
 use tower_http::trace::TraceLayer;
 use tracing::error;
 
-use crate::{auth::Authenticator, passwords::PasswordChecker, sessions::SessionManager};
+use crate::{
+    auth::Authenticator, metrics::Metrics, passwords::PasswordChecker, ratelimit::RateLimiter,
+    sessions::SessionManager, totp::TotpChecker,
+};
 
 pub use crate::{
+    audit::AUDIT_TARGET,
     config::*,
-    datastore::{Datastore, DatastoreError, ReadMode, WriteMode},
-    login::LoginForm,
+    datastore::{
+        check_datastore_conformance, ClosingEvent, Datastore, DatastoreError, DatastoreOptions,
+        MigrationReport, ReadMode, SessionFilter, SessionStore, WriteMode,
+    },
+    login::{LoginForm, LoginResponse},
     passwords::hash_password,
+    ratelimit::RateLimitConfig,
+    sessions::{LoginMethod, SessionData, SessionId, SessionInfo, SessionSecretHash},
+    signed_sessions::{SessionKeys, SessionKeysError},
+    totp::{TotpSecret, TotpSecretError},
 };
 
+mod audit;
 mod auth;
 mod config;
 mod datastore;
+mod health;
 mod login;
+mod metrics;
 mod passwords;
+mod ratelimit;
 mod sessions;
+mod signed_sessions;
+mod totp;
 
 #[derive(Clone)]
 struct AppState {
-    config: AppConfig,
+    config: Arc<RwLock<AppConfig>>,
+    datastore: Arc<Datastore>,
     authenticator: Arc<Authenticator>,
     password_checker: Arc<PasswordChecker>,
     session_manager: Arc<SessionManager>,
+    totp_checker: Arc<TotpChecker>,
+    rate_limiter: Arc<RateLimiter>,
+    metrics: Arc<Metrics>,
+}
+
+impl AppState {
+    fn config(&self) -> RwLockReadGuard<'_, AppConfig> {
+        self.config.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl FromRef<AppState> for AppConfig {
+    fn from_ref(input: &AppState) -> Self {
+        input.config().clone()
+    }
 }
 
 impl FromRef<AppState> for AuthConfig {
     fn from_ref(input: &AppState) -> Self {
-        input.config.auth_config.clone()
+        input.config().auth_config.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Datastore> {
+    fn from_ref(input: &AppState) -> Self {
+        input.datastore.clone()
     }
 }
 
@@ -59,48 +102,178 @@ impl FromRef<AppState> for Arc<SessionManager> {
     }
 }
 
+impl FromRef<AppState> for Arc<TotpChecker> {
+    fn from_ref(input: &AppState) -> Self {
+        input.totp_checker.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<RateLimiter> {
+    fn from_ref(input: &AppState) -> Self {
+        input.rate_limiter.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Metrics> {
+    fn from_ref(input: &AppState) -> Self {
+        input.metrics.clone()
+    }
+}
+
 #[derive(Debug, Error)]
 enum AppError {
     #[error("{0}")]
     DatastoreError(#[from] DatastoreError),
+    #[error("too many failed attempts, retry after {0:?}")]
+    RateLimited(Duration),
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let status = match self {
+        match self {
             Self::DatastoreError(e) => {
                 error!("Error from datastore: {e}");
-                StatusCode::INTERNAL_SERVER_ERROR
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+            Self::RateLimited(retry_after) => {
+                // Round up so clients don't retry while still locked out
+                let retry_after = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+
+                (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, HeaderValue::from(retry_after))],
+                )
+                    .into_response()
             }
-        };
+        }
+    }
+}
+
+/// Handle for updating the config of a running app, see [`app_with_handle`].
+#[derive(Clone)]
+pub struct AppHandle {
+    config: Arc<RwLock<AppConfig>>,
+    session_manager: Arc<SessionManager>,
+    metrics: Arc<Metrics>,
+    datastore: Arc<Datastore>,
+}
+
+impl AppHandle {
+    /// Replace the `AuthConfig` used by the app.
+    ///
+    /// Requests already in progress finish with the previous config, and existing sessions are
+    /// kept (but are subject to the new `session_expiry`, `session_idle_timeout`, `session_keys`
+    /// and `revoked_sessions`).
+    pub fn set_auth_config(&self, auth_config: AuthConfig) {
+        self.session_manager.set_auth_config(&auth_config);
+        self.config
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .auth_config = auth_config;
+    }
+
+    /// Create a router serving Prometheus metrics for the app at `/metrics`.
+    ///
+    /// This is separate from the app so it can be served on a different address, which isn't
+    /// exposed through the reverse proxy.
+    pub fn metrics_app(&self) -> Router {
+        Router::new()
+            .route("/metrics", get(metrics::handle_metrics))
+            .with_state((self.metrics.clone(), self.datastore.clone()))
+    }
+
+    /// A future which periodically deletes expired sessions from the datastore, or `None` if
+    /// `session_sweep_interval` isn't set.
+    ///
+    /// The app doesn't spawn this itself, so it can be created outside a runtime. The future
+    /// finishes once the app and all its handles are dropped.
+    pub fn session_sweeper(&self) -> Option<impl Future<Output = ()> + Send + 'static> {
+        let interval = self
+            .config
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .session_sweep_interval
+            .filter(|interval| interval.is_positive())?;
 
-        status.into_response()
+        Some(sessions::sweep_sessions(
+            &self.session_manager,
+            interval.unsigned_abs(),
+        ))
+    }
+
+    /// The datastore used by the app.
+    pub fn datastore(&self) -> &Arc<Datastore> {
+        &self.datastore
     }
 }
 
-pub fn app(config: AppConfig, datastore: Datastore) -> Router {
+/// Create the app. Expired sessions aren't deleted from the datastore, use [`app_with_handle`]
+/// and spawn [`AppHandle::session_sweeper`] for that.
+pub fn app(config: AppConfig, datastore: impl Into<Datastore>) -> Router {
+    app_with_handle(config, datastore).0
+}
+
+/// Create the app along with an [`AppHandle`] for updating its config while it's running.
+pub fn app_with_handle(config: AppConfig, datastore: impl Into<Datastore>) -> (Router, AppHandle) {
+    let mut datastore = datastore.into();
+    let metrics = Arc::new(Metrics::new());
+    datastore.set_metrics(metrics.clone());
+    let datastore = Arc::new(datastore);
     let password_checker = Arc::new(PasswordChecker::default());
-    let session_manager = Arc::new(SessionManager::new(
-        config.auth_config.session_expiry,
-        Arc::from(datastore),
-    ));
+    let session_manager = Arc::new(SessionManager::new(&config.auth_config, datastore.clone()));
+    let rate_limiter = Arc::new(RateLimiter::new(config.rate_limit.clone()));
     let authenticator = Arc::new(Authenticator::new(
         config.public_path.clone(),
         password_checker.clone(),
         session_manager.clone(),
+        rate_limiter.clone(),
+        metrics.clone(),
     ));
 
-    Router::new()
+    let routes = Router::new()
+        .route("/health", get(health::handle_health))
+        .route("/ready", get(health::handle_ready))
         .route("/auth_request", any(auth::handle_auth_request))
+        .route("/forward_auth", any(auth::handle_forward_auth))
+        .route(&config.ext_authz_prefix, any(auth::handle_ext_authz))
+        .route(
+            &format!("{}/", config.ext_authz_prefix),
+            any(auth::handle_ext_authz),
+        )
+        .route(
+            &format!("{}/{{*path}}", config.ext_authz_prefix),
+            any(auth::handle_ext_authz),
+        )
         .route(
             &format!("{}/login", config.public_path),
             get(login::handle_get_login).post(login::handle_post_login),
         )
+        .route(
+            &format!("{}/logout", config.public_path),
+            post(login::handle_logout),
+        );
+
+    let config = Arc::new(RwLock::new(config));
+    let router = routes
         .with_state(AppState {
-            config,
+            config: config.clone(),
+            datastore: datastore.clone(),
             authenticator,
             password_checker,
-            session_manager,
+            session_manager: session_manager.clone(),
+            totp_checker: Default::default(),
+            rate_limiter,
+            metrics: metrics.clone(),
         })
-        .layer(TraceLayer::new_for_http())
+        .layer(TraceLayer::new_for_http());
+
+    (
+        router,
+        AppHandle {
+            config,
+            session_manager,
+            metrics,
+            datastore,
+        },
+    )
 }
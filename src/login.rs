@@ -1,9 +1,9 @@
-use std::sync::Arc;
+use std::{borrow::Cow, sync::Arc};
 
 use axum::{
-    extract::State,
-    http::StatusCode,
-    response::{Html, IntoResponse, Response},
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode, Uri},
+    response::{Html, IntoResponse, Redirect, Response},
     Json,
 };
 use axum_extra::extract::{
@@ -11,53 +11,191 @@ use axum_extra::extract::{
     CookieJar,
 };
 use serde::{Deserialize, Serialize};
-use tracing::debug;
+use time::Duration;
+use tracing::{debug, warn};
 
 use crate::{
-    config::{AuthConfig, SessionExpiry},
+    audit::{audit, AuditRequest},
+    config::{AppConfig, AuthConfig},
+    metrics::Metrics,
     passwords::PasswordChecker,
-    sessions::{SessionManager, SessionToken},
+    ratelimit::{ClientIp, RateLimiter},
+    sessions::{LoginMethod, SessionManager},
+    totp::TotpChecker,
+    AppError,
 };
 
 static LOGIN_HTML: &str = include_str!("../frontend/login.html");
+static TOTP_PLACEHOLDER: &str = "<!-- totp -->";
+static TOTP_INPUT: &str = r#"<input type="text" name="totp" required placeholder="Code" inputmode="numeric" autocomplete="one-time-code" pattern="[0-9]{6}" />"#;
 
-pub async fn handle_get_login() -> Response {
-    Html(LOGIN_HTML).into_response()
+pub async fn handle_get_login(State(auth_config): State<AuthConfig>) -> Response {
+    let html = if auth_config.totp_secret.is_some() {
+        Cow::Owned(LOGIN_HTML.replace(TOTP_PLACEHOLDER, TOTP_INPUT))
+    } else {
+        Cow::Borrowed(LOGIN_HTML)
+    };
+
+    Html(html).into_response()
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct LoginForm {
     pub password: String,
+    /// The current TOTP code, required if a TOTP secret is configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct LoginResponse {
+    /// Where the browser should go after logging in, validated by the server.
+    pub redirect_to: String,
 }
 
+#[derive(Deserialize)]
+pub struct LoginQuery {
+    redirect_to: Option<String>,
+}
+
+const DEFAULT_REDIRECT: &str = "/";
+
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_post_login(
     State(auth_config): State<AuthConfig>,
     State(password_checker): State<Arc<PasswordChecker>>,
     State(session_manager): State<Arc<SessionManager>>,
+    State(totp_checker): State<Arc<TotpChecker>>,
+    State(rate_limiter): State<Arc<RateLimiter>>,
+    State(metrics): State<Arc<Metrics>>,
+    client_ip: ClientIp,
+    Query(query): Query<LoginQuery>,
+    headers: HeaderMap,
     cookie_jar: CookieJar,
     Json(form): Json<LoginForm>,
 ) -> axum::response::Result<Response> {
+    let audit_request = AuditRequest::new(client_ip, &headers, query.redirect_to.as_deref());
+
+    if let Err(retry_after) = rate_limiter.check(client_ip) {
+        metrics.login("rate_limited");
+        audit!("login_failed", &audit_request, reason = "rate_limited");
+        return Err(AppError::RateLimited(retry_after).into());
+    }
+
     if !password_checker
         .check_password(&form.password, &auth_config.password)
         .await
     {
         debug!("Login: invalid");
+        metrics.login("invalid_password");
+        audit!("login_failed", &audit_request, reason = "invalid_password");
+        rate_limiter.record_failure(client_ip);
         return Ok(StatusCode::UNAUTHORIZED.into_response());
     }
 
+    let mut login_method = LoginMethod::Password;
+
+    if let Some(totp_secret) = &auth_config.totp_secret {
+        login_method = LoginMethod::PasswordTotp;
+        let totp = form.totp.as_deref().unwrap_or_default();
+        if !totp_checker.check_code(totp, totp_secret) {
+            debug!("Login: invalid TOTP code");
+            metrics.login("invalid_totp");
+            audit!("login_failed", &audit_request, reason = "invalid_totp");
+            rate_limiter.record_failure(client_ip);
+            return Ok(StatusCode::UNAUTHORIZED.into_response());
+        }
+    }
+
     debug!("Login: valid");
+    metrics.login("valid");
+    rate_limiter.record_success(client_ip);
 
-    let session_token = session_manager.create_session().await?;
-    let session_cookie = create_session_cookie(&auth_config, session_token);
+    let session = session_manager
+        .create_session(client_ip, audit_request.user_agent, login_method)
+        .await?;
+    audit!("login", &audit_request, session_id = session.id.0);
+    let session_cookie = create_session_cookie(
+        &auth_config,
+        session.token,
+        session_manager.cookie_max_age(),
+    );
+
+    let redirect_to = match query.redirect_to {
+        Some(redirect_to) if is_allowed_redirect(&auth_config, &headers, &redirect_to) => {
+            redirect_to
+        }
+        Some(redirect_to) => {
+            warn!("Login: ignoring disallowed redirect to {:?}", redirect_to);
+            DEFAULT_REDIRECT.into()
+        }
+        None => DEFAULT_REDIRECT.into(),
+    };
 
-    Ok((cookie_jar.add(session_cookie.into_owned()), StatusCode::OK).into_response())
+    Ok((
+        cookie_jar.add(session_cookie.into_owned()),
+        Json(LoginResponse { redirect_to }),
+    )
+        .into_response())
 }
 
-fn create_session_cookie(auth_config: &AuthConfig, session_token: SessionToken) -> Cookie<'static> {
-    let mut session_cookie = Cookie::<'static>::new(
-        auth_config.session_cookie_name.clone(),
-        session_token.encode(),
-    );
+pub async fn handle_logout(
+    State(config): State<AppConfig>,
+    State(session_manager): State<Arc<SessionManager>>,
+    client_ip: ClientIp,
+    headers: HeaderMap,
+    cookie_jar: CookieJar,
+) -> axum::response::Result<Response> {
+    let auth_config = &config.auth_config;
+
+    if let Some(session_cookie) = cookie_jar.get(&auth_config.session_cookie_name) {
+        let deleted = session_manager
+            .delete_session(session_cookie.value())
+            .await?;
+        debug!(
+            "Logout: {}",
+            if deleted.is_some() {
+                "deleted"
+            } else {
+                "no session"
+            }
+        );
+
+        if let Some(id) = deleted {
+            audit!(
+                "logout",
+                &AuditRequest::new(client_ip, &headers, None),
+                session_id = id.0
+            );
+        }
+    }
+
+    let login_uri = format!("{}/login", config.public_path);
+
+    Ok((
+        cookie_jar.remove(session_cookie(auth_config, String::new())),
+        Redirect::to(&login_uri),
+    )
+        .into_response())
+}
+
+/// Create the session cookie, which is a browser session cookie if `max_age` is `None`.
+pub(crate) fn create_session_cookie(
+    auth_config: &AuthConfig,
+    value: String,
+    max_age: Option<Duration>,
+) -> Cookie<'static> {
+    let mut session_cookie = session_cookie(auth_config, value);
+
+    if let Some(max_age) = max_age {
+        session_cookie.set_max_age(max_age);
+    }
+
+    session_cookie
+}
+
+fn session_cookie(auth_config: &AuthConfig, value: String) -> Cookie<'static> {
+    let mut session_cookie = Cookie::<'static>::new(auth_config.session_cookie_name.clone(), value);
 
     session_cookie.set_path("/");
     session_cookie.set_same_site(SameSite::Lax);
@@ -68,9 +206,60 @@ fn create_session_cookie(auth_config: &AuthConfig, session_token: SessionToken)
         session_cookie.set_domain(domain.clone());
     }
 
-    if let SessionExpiry::Duration(expiry) = auth_config.session_expiry {
-        session_cookie.set_max_age(expiry);
+    session_cookie
+}
+
+fn is_allowed_redirect(auth_config: &AuthConfig, headers: &HeaderMap, redirect_to: &str) -> bool {
+    if redirect_to
+        .chars()
+        .any(|c| c.is_ascii_control() || c == '\\')
+    {
+        return false;
     }
 
-    session_cookie
+    // Relative URIs are always on the same host, but "//host/path" is protocol-relative
+    if let Some(path) = redirect_to.strip_prefix('/') {
+        return !path.starts_with('/') && redirect_to.parse::<Uri>().is_ok();
+    }
+
+    let uri = match redirect_to.parse::<Uri>() {
+        Ok(uri) => uri,
+        Err(_) => return false,
+    };
+
+    if !matches!(uri.scheme_str(), Some("http" | "https")) {
+        return false;
+    }
+
+    let host = match uri.host() {
+        Some(host) => host,
+        None => return false,
+    };
+
+    is_same_host(headers, host)
+        || is_cookie_domain_host(auth_config, host)
+        || auth_config
+            .allowed_redirect_hosts
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host))
+}
+
+fn is_same_host(headers: &HeaderMap, host: &str) -> bool {
+    headers
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| format!("http://{}", h).parse::<Uri>().ok())
+        .is_some_and(|uri| uri.host().is_some_and(|h| h.eq_ignore_ascii_case(host)))
+}
+
+fn is_cookie_domain_host(auth_config: &AuthConfig, host: &str) -> bool {
+    let domain = match &auth_config.session_cookie_domain {
+        Some(domain) => domain.trim_start_matches('.'),
+        None => return false,
+    };
+
+    let host = host.to_ascii_lowercase();
+    let domain = domain.to_ascii_lowercase();
+
+    host == domain || host.ends_with(&format!(".{}", domain))
 }
@@ -1,24 +1,19 @@
-use clap::Parser;
-use tracing::level_filters::LevelFilter;
-use tracing_subscriber::{EnvFilter, FmtSubscriber};
+use std::env;
 
 use self::cli::{Cli, Cmd};
 
 mod cli;
 
 fn main() {
-    FmtSubscriber::builder()
-        .with_env_filter(
-            EnvFilter::builder()
-                .with_env_var("DUMB_AUTH_LOG")
-                .with_default_directive(LevelFilter::INFO.into())
-                .from_env_lossy(),
-        )
-        .init();
+    let cli = Cli::try_parse_with_config(env::args_os()).unwrap_or_else(|e| e.exit());
+    cli::init_logging(&cli);
 
-    let cli = Cli::parse();
     match cli.cmd {
         None => cli::run(cli.args.unwrap()),
         Some(Cmd::Passwd(args)) => cli::passwd(args),
+        Some(Cmd::Totp(args)) => cli::totp(args),
+        Some(Cmd::Healthcheck(args)) => cli::healthcheck(args),
+        Some(Cmd::Sessions(args)) => cli::sessions(args),
+        Some(Cmd::Datastore(args)) => cli::datastore(args),
     };
 }
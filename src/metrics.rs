@@ -0,0 +1,160 @@
+use std::{sync::Arc, time::Instant};
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use prometheus_client::{
+    encoding::{text::encode, EncodeLabelSet},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::Registry,
+};
+use tracing::error;
+
+use crate::datastore::Datastore;
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ResultLabels {
+    result: &'static str,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct MethodLabels {
+    method: &'static str,
+    result: &'static str,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct OperationLabels {
+    operation: &'static str,
+}
+
+type HistogramFamily<S> = Family<S, Histogram, fn() -> Histogram>;
+
+/// Prometheus metrics for the app, served separately by [`crate::AppHandle::metrics_app`].
+pub(crate) struct Metrics {
+    registry: Registry,
+    auth_requests: Family<ResultLabels, Counter>,
+    auth_methods: Family<MethodLabels, Counter>,
+    logins: Family<ResultLabels, Counter>,
+    datastore_duration: HistogramFamily<OperationLabels>,
+    sessions: Gauge,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let mut registry = Registry::with_prefix("dumb_auth");
+
+        let auth_requests = Family::default();
+        registry.register(
+            "auth_requests",
+            "Auth requests from the reverse proxy, by result",
+            auth_requests.clone(),
+        );
+
+        let auth_methods = Family::default();
+        registry.register(
+            "auth_method_checks",
+            "Auth method checks during auth requests, by method and result",
+            auth_methods.clone(),
+        );
+
+        let logins = Family::default();
+        registry.register(
+            "logins",
+            "Interactive login attempts, by result",
+            logins.clone(),
+        );
+
+        // 50us to ~1.6s
+        let datastore_duration: HistogramFamily<_> =
+            Family::new_with_constructor(|| Histogram::new(exponential_buckets(0.00005, 2.0, 16)));
+        registry.register(
+            "datastore_operation_duration_seconds",
+            "Time taken by datastore operations, by operation",
+            datastore_duration.clone(),
+        );
+
+        let sessions = Gauge::default();
+        registry.register(
+            "sessions",
+            "Sessions in the datastore, including expired sessions not yet removed",
+            sessions.clone(),
+        );
+
+        Self {
+            registry,
+            auth_requests,
+            auth_methods,
+            logins,
+            datastore_duration,
+            sessions,
+        }
+    }
+
+    /// Count an auth request, where `result` is `valid`, `invalid` or `rate_limited`.
+    pub fn auth_request(&self, result: &'static str) {
+        self.auth_requests
+            .get_or_create(&ResultLabels { result })
+            .inc();
+    }
+
+    pub fn auth_method(&self, method: &'static str, valid: bool) {
+        let result = if valid { "valid" } else { "invalid" };
+        self.auth_methods
+            .get_or_create(&MethodLabels { method, result })
+            .inc();
+    }
+
+    /// Count a login attempt, where `result` is `valid`, `invalid_password`, `invalid_totp` or
+    /// `rate_limited`.
+    pub fn login(&self, result: &'static str) {
+        self.logins.get_or_create(&ResultLabels { result }).inc();
+    }
+
+    /// Record how long a datastore operation took since `start`.
+    pub fn datastore_operation(&self, operation: &'static str, start: Instant) {
+        self.datastore_duration
+            .get_or_create(&OperationLabels { operation })
+            .observe(start.elapsed().as_secs_f64());
+    }
+
+    fn encode(&self) -> Result<String, std::fmt::Error> {
+        let mut body = String::new();
+        encode(&mut body, &self.registry)?;
+        Ok(body)
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub async fn handle_metrics(
+    State((metrics, datastore)): State<(Arc<Metrics>, Arc<Datastore>)>,
+) -> Response {
+    match datastore.count_sessions().await {
+        Ok(count) => {
+            metrics.sessions.set(count.try_into().unwrap_or(i64::MAX));
+        }
+        Err(e) => error!("Error counting sessions for metrics: {e}"),
+    }
+
+    match metrics.encode() {
+        Ok(body) => ([(header::CONTENT_TYPE, CONTENT_TYPE)], body).into_response(),
+        Err(e) => {
+            error!("Error encoding metrics: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::request::Parts,
+};
+use tracing::{debug, warn};
+
+use crate::AppState;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    /// Number of failed attempts allowed before a client is locked out.
+    pub max_attempts: u32,
+    /// How long a client is locked out for after reaching `max_attempts`. Doubles with every
+    /// subsequent failure.
+    pub lockout: Duration,
+    /// The longest a client can be locked out for. Failed attempts are also forgotten after this
+    /// long without any more failures.
+    pub max_lockout: Duration,
+}
+
+impl RateLimitConfig {
+    pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+    pub const DEFAULT_LOCKOUT: Duration = Duration::from_secs(60);
+    pub const DEFAULT_MAX_LOCKOUT: Duration = Duration::from_secs(60 * 60);
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: Self::DEFAULT_MAX_ATTEMPTS,
+            lockout: Self::DEFAULT_LOCKOUT,
+            max_lockout: Self::DEFAULT_MAX_LOCKOUT,
+        }
+    }
+}
+
+/// Limits how often each client can fail password checks.
+pub(crate) struct RateLimiter {
+    config: Option<RateLimitConfig>,
+    clients: Mutex<HashMap<IpAddr, ClientState>>,
+}
+
+struct ClientState {
+    failures: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl RateLimiter {
+    /// Only prune forgotten clients once there are at least this many.
+    const PRUNE_THRESHOLD: usize = 1024;
+
+    pub fn new(config: Option<RateLimitConfig>) -> Self {
+        Self {
+            config,
+            clients: Default::default(),
+        }
+    }
+
+    /// Check if the client is allowed to attempt authentication, otherwise returns how long until
+    /// they can try again.
+    pub fn check(&self, client_ip: ClientIp) -> Result<(), Duration> {
+        let (ClientIp(Some(ip)), Some(_)) = (client_ip, &self.config) else {
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        match self.clients().get(&ip).and_then(|state| state.locked_until) {
+            Some(locked_until) if locked_until > now => Err(locked_until - now),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn record_failure(&self, client_ip: ClientIp) {
+        let (ClientIp(Some(ip)), Some(config)) = (client_ip, &self.config) else {
+            return;
+        };
+
+        let now = Instant::now();
+        let mut clients = self.clients();
+
+        if clients.len() >= Self::PRUNE_THRESHOLD {
+            clients.retain(|_, state| !state.is_forgotten(config, now));
+        }
+
+        let state = clients.entry(ip).or_insert(ClientState {
+            failures: 0,
+            last_failure: now,
+            locked_until: None,
+        });
+
+        if state.is_forgotten(config, now) {
+            state.failures = 0;
+        }
+
+        state.failures = state.failures.saturating_add(1);
+        state.last_failure = now;
+
+        if state.failures >= config.max_attempts {
+            let doublings = (state.failures - config.max_attempts).min(31);
+            let lockout = config
+                .lockout
+                .saturating_mul(1 << doublings)
+                .min(config.max_lockout);
+
+            warn!(
+                "Locking out {} for {}s after {} failed attempts",
+                ip,
+                lockout.as_secs(),
+                state.failures
+            );
+            state.locked_until = Some(now + lockout);
+        } else {
+            debug!("Failed attempt {} from {}", state.failures, ip);
+        }
+    }
+
+    pub fn record_success(&self, client_ip: ClientIp) {
+        if let ClientIp(Some(ip)) = client_ip {
+            self.clients().remove(&ip);
+        }
+    }
+
+    fn clients(&self) -> MutexGuard<'_, HashMap<IpAddr, ClientState>> {
+        self.clients.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl ClientState {
+    fn is_forgotten(&self, config: &RateLimitConfig, now: Instant) -> bool {
+        let locked = self.locked_until.is_some_and(|until| until > now);
+        !locked && now.duration_since(self.last_failure) >= config.max_lockout
+    }
+}
+
+/// The IP address of the client, from `AppConfig::client_ip_header` if configured, otherwise from
+/// the connection (if available).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ClientIp(pub Option<IpAddr>);
+
+impl FromRequestParts<AppState> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(header) = &state.config().client_ip_header {
+            // Proxies append to X-Forwarded-For, so the last address is the one the proxy saw
+            let ip = parts
+                .headers
+                .get_all(header)
+                .iter()
+                .next_back()
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.rsplit(',').next())
+                .and_then(|ip| ip.trim().parse().ok());
+
+            return Ok(Self(ip));
+        }
+
+        Ok(Self(
+            parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip()),
+        ))
+    }
+}
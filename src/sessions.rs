@@ -1,68 +1,478 @@
-use std::{fmt, sync::Arc, time::SystemTime};
+use std::{
+    fmt,
+    future::Future,
+    net::IpAddr,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
 
 use base64ct::{Base64UrlUnpadded, Encoding};
 use bincode::Options;
 use rand::{thread_rng, RngCore};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use subtle::ConstantTimeEq;
 use thiserror::Error;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, error, info};
 
-use crate::{config::SessionExpiry, datastore::Datastore, AppError};
+use crate::{
+    audit::audit,
+    config::{AuthConfig, SessionExpiry},
+    datastore::Datastore,
+    ratelimit::ClientIp,
+    signed_sessions::{SessionKeys, SignedSession},
+    AppError,
+};
 
+/// Creates and checks sessions, which are either stored in the datastore or, if there are
+/// [`AuthConfig::session_keys`], carried by signed tokens.
 pub(crate) struct SessionManager {
-    expiry: SessionExpiry,
+    settings: RwLock<Arc<SessionSettings>>,
     datastore: Arc<Datastore>,
 }
 
 impl SessionManager {
-    pub fn new(expiry: SessionExpiry, datastore: Arc<Datastore>) -> Self {
-        Self { expiry, datastore }
+    /// How often to update a session's `last_used` time, to avoid a write on every request.
+    const LAST_USED_INTERVAL: Duration = Duration::from_secs(60);
+    /// Longer user agents are truncated before being stored.
+    const MAX_USER_AGENT_LEN: usize = 512;
+
+    pub fn new(auth_config: &AuthConfig, datastore: Arc<Datastore>) -> Self {
+        Self {
+            settings: RwLock::new(Arc::new(SessionSettings::new(auth_config))),
+            datastore,
+        }
+    }
+
+    pub fn set_auth_config(&self, auth_config: &AuthConfig) {
+        *self.settings.write().unwrap_or_else(|e| e.into_inner()) =
+            Arc::new(SessionSettings::new(auth_config));
     }
 
-    pub async fn create_session(&self) -> Result<SessionToken, AppError> {
-        let secret = SessionSecret::generate();
+    fn settings(&self) -> Arc<SessionSettings> {
+        self.settings
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
 
-        let id = self
-            .datastore
-            .create_session(SessionData {
-                secret: secret.clone(),
-                created: SystemTime::now(),
-            })
-            .await?;
+    /// The `Max-Age` for the cookie of a session created now.
+    pub fn cookie_max_age(&self) -> Option<time::Duration> {
+        let now = SystemTime::now();
+        self.settings().lifetime.cookie_max_age(now, now)
+    }
+
+    pub async fn create_session(
+        &self,
+        client_ip: ClientIp,
+        user_agent: Option<&str>,
+        login_method: LoginMethod,
+    ) -> Result<NewSession, AppError> {
+        let settings = self.settings();
+        let now = SystemTime::now();
+
+        let session = match &settings.keys {
+            Some(keys) => {
+                let session = SignedSession {
+                    id: SessionId(thread_rng().next_u64()),
+                    created: now,
+                    expires: settings.lifetime.expires(now),
+                    last_used: now,
+                    login_method,
+                };
+
+                NewSession {
+                    id: session.id,
+                    token: session.sign(keys),
+                }
+            }
+            None => {
+                let secret = SessionSecret::generate();
+                let id = self
+                    .datastore
+                    .create_session(SessionData {
+                        secret_hash: secret.hash(),
+                        created: now,
+                        client_ip: client_ip.0,
+                        user_agent: user_agent.map(|user_agent| {
+                            truncate(user_agent, Self::MAX_USER_AGENT_LEN).to_string()
+                        }),
+                        last_used: now,
+                        login_method: Some(login_method),
+                    })
+                    .await?;
+
+                NewSession {
+                    id,
+                    token: SessionToken { id, secret }.encode(),
+                }
+            }
+        };
+
+        audit!("session_created"; session_id = session.id.0);
+        Ok(session)
+    }
+
+    /// Check if the session token is valid, returning the session if it is.
+    ///
+    /// Also updates the session's `last_used` time if it hasn't been updated recently, which
+    /// renews the session if there's an idle timeout.
+    pub async fn check_session(&self, token: &str) -> Result<Option<ValidSession>, AppError> {
+        let settings = self.settings();
+        if let Some(keys) = &settings.keys {
+            return Ok(check_signed_session(&settings, keys, token));
+        }
+
+        let (token, data) = match self.find_session(token).await? {
+            Some(session) => session,
+            None => return Ok(None),
+        };
+
+        let lifetime = settings.lifetime;
+        if data.is_expired(lifetime) {
+            if self.datastore.delete_session(token.id).await? {
+                audit!("session_expired"; session_id = token.id.0);
+            }
+            return Ok(None);
+        }
 
-        Ok(SessionToken { id, secret })
+        let mut renewed = None;
+        if data.last_used.elapsed().unwrap_or_default() >= lifetime.last_used_interval() {
+            let now = SystemTime::now();
+            self.datastore.touch_session(token.id, now).await?;
+
+            if lifetime.idle_timeout.is_some() {
+                renewed =
+                    lifetime
+                        .cookie_max_age(data.created, now)
+                        .map(|max_age| RenewedSession {
+                            token: token.encode(),
+                            max_age: Some(max_age),
+                        });
+            }
+        }
+
+        Ok(Some(ValidSession {
+            id: token.id,
+            renewed,
+        }))
     }
 
-    pub async fn check_session(&self, token: &str) -> Result<bool, AppError> {
+    /// Delete the session if the token is valid, returning the deleted session's ID.
+    ///
+    /// Signed sessions can't be deleted, so only their ID is returned. They remain valid until
+    /// they expire, unless revoked with [`AuthConfig::revoked_sessions`].
+    pub async fn delete_session(&self, token: &str) -> Result<Option<SessionId>, AppError> {
+        if let Some(keys) = &self.settings().keys {
+            return Ok(SignedSession::verify(token, keys).map(|(session, _)| session.id));
+        }
+
+        let (token, _) = match self.find_session(token).await? {
+            Some(session) => session,
+            None => return Ok(None),
+        };
+
+        if !self.datastore.delete_session(token.id).await? {
+            return Ok(None);
+        }
+
+        audit!("session_revoked"; session_id = token.id.0);
+        Ok(Some(token.id))
+    }
+
+    pub async fn delete_expired_sessions(&self) -> Result<u64, AppError> {
+        let lifetime = self.settings().lifetime;
+        if lifetime.expiry == SessionExpiry::Session && lifetime.idle_timeout.is_none() {
+            // Sessions never expire on the server
+            return Ok(0);
+        }
+
+        Ok(self
+            .datastore
+            .delete_sessions(Box::new(move |_, data| data.is_expired(lifetime)))
+            .await?)
+    }
+
+    async fn find_session(
+        &self,
+        token: &str,
+    ) -> Result<Option<(SessionToken, SessionData)>, AppError> {
         let token = match SessionToken::decode(token) {
             Ok(token) => token,
-            Err(_) => return Ok(false),
+            Err(_) => return Ok(None),
         };
 
         let data = match self.datastore.read_session(token.id).await? {
             Some(data) => data,
-            None => return Ok(false),
+            None => return Ok(None),
         };
 
-        if !data.secret.verify(&token.secret) {
-            return Ok(false);
+        if !token.secret.verify(&data.secret_hash) {
+            return Ok(None);
         }
 
-        if let SessionExpiry::Duration(expiry) = self.expiry {
-            if data.created.elapsed().unwrap_or_default() >= expiry {
-                self.datastore.delete_session(token.id).await?;
-                return Ok(false);
+        Ok(Some((token, data)))
+    }
+}
+
+/// Periodically delete expired sessions until the [`SessionManager`] is dropped, starting one
+/// `period` from now.
+pub fn sweep_sessions(
+    session_manager: &Arc<SessionManager>,
+    period: Duration,
+) -> impl Future<Output = ()> + Send + 'static {
+    let session_manager = Arc::downgrade(session_manager);
+    let start = tokio::time::Instant::now() + period;
+
+    async move {
+        let mut interval = tokio::time::interval_at(start, period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            let session_manager = match session_manager.upgrade() {
+                Some(session_manager) => session_manager,
+                None => break,
+            };
+
+            match session_manager.delete_expired_sessions().await {
+                Ok(0) => debug!("No expired sessions to remove"),
+                Ok(count) => {
+                    info!("Removed {} expired session(s)", count);
+                    audit!("sessions_expired"; count);
+                }
+                Err(e) => error!("Error removing expired sessions: {}", e),
             }
         }
+    }
+}
+
+/// Check a signed session, which only needs the keys and not the datastore.
+///
+/// The session is renewed by re-signing it, which also moves sessions signed with an older key to
+/// the newest key.
+fn check_signed_session(
+    settings: &SessionSettings,
+    keys: &SessionKeys,
+    token: &str,
+) -> Option<ValidSession> {
+    let (mut session, newest_key) = SignedSession::verify(token, keys)?;
+
+    if settings.revoked_sessions.contains(&session.id) {
+        debug!("Rejected revoked session {}", session.id);
+        return None;
+    }
+
+    let now = SystemTime::now();
+    let lifetime = settings.lifetime;
+    if session.expires.is_some_and(|expires| expires <= now)
+        || lifetime.is_expired(session.created, session.last_used)
+    {
+        return None;
+    }
+
+    let idle = lifetime.idle_timeout.is_some()
+        && session.last_used.elapsed().unwrap_or_default() >= lifetime.last_used_interval();
+    let renewed = (idle || !newest_key).then(|| {
+        session.last_used = now;
+        RenewedSession {
+            token: session.sign(keys),
+            max_age: lifetime.cookie_max_age(session.created, now),
+        }
+    });
+
+    Some(ValidSession {
+        id: session.id,
+        renewed,
+    })
+}
+
+/// A session created by [`SessionManager::create_session`].
+pub(crate) struct NewSession {
+    pub id: SessionId,
+    /// Token to set as the session cookie.
+    pub token: String,
+}
+
+/// A session that [`SessionManager::check_session`] found to be valid.
+pub(crate) struct ValidSession {
+    pub id: SessionId,
+    /// Set if the session was renewed, and the session cookie should be re-issued.
+    pub renewed: Option<RenewedSession>,
+}
+
+pub(crate) struct RenewedSession {
+    pub token: String,
+    pub max_age: Option<time::Duration>,
+}
+
+/// The parts of the [`AuthConfig`] used for sessions.
+struct SessionSettings {
+    lifetime: SessionLifetime,
+    keys: Option<SessionKeys>,
+    revoked_sessions: Vec<SessionId>,
+}
+
+impl SessionSettings {
+    fn new(auth_config: &AuthConfig) -> Self {
+        Self {
+            lifetime: SessionLifetime {
+                expiry: auth_config.session_expiry,
+                idle_timeout: auth_config.session_idle_timeout,
+            },
+            keys: auth_config.session_keys.clone(),
+            revoked_sessions: auth_config.revoked_sessions.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct SessionLifetime {
+    expiry: SessionExpiry,
+    idle_timeout: Option<time::Duration>,
+}
+
+impl SessionLifetime {
+    /// When a session created at `created` expires regardless of use, if it does on the server.
+    fn expires(&self, created: SystemTime) -> Option<SystemTime> {
+        match self.expiry {
+            SessionExpiry::Session => None,
+            SessionExpiry::Duration(expiry) => Some(created + expiry.unsigned_abs()),
+        }
+    }
+
+    fn is_expired(&self, created: SystemTime, last_used: SystemTime) -> bool {
+        let expired = match self.expiry {
+            SessionExpiry::Session => false,
+            SessionExpiry::Duration(expiry) => created.elapsed().unwrap_or_default() >= expiry,
+        };
+        let idle = self
+            .idle_timeout
+            .is_some_and(|idle_timeout| last_used.elapsed().unwrap_or_default() >= idle_timeout);
+
+        expired || idle
+    }
+
+    /// How often to update `last_used`, often enough for the idle timeout to be accurate.
+    fn last_used_interval(&self) -> Duration {
+        match self
+            .idle_timeout
+            .map(|idle_timeout| idle_timeout.unsigned_abs() / 2)
+        {
+            Some(interval) => interval.min(SessionManager::LAST_USED_INTERVAL),
+            None => SessionManager::LAST_USED_INTERVAL,
+        }
+    }
+
+    /// The `Max-Age` for the cookie of a session created at `created`, as of `now`.
+    ///
+    /// Browser session cookies don't have a `Max-Age`, even with an idle timeout, since the idle
+    /// timeout is enforced by the server anyway.
+    fn cookie_max_age(&self, created: SystemTime, now: SystemTime) -> Option<time::Duration> {
+        let SessionExpiry::Duration(expiry) = self.expiry else {
+            return None;
+        };
+
+        let age = now.duration_since(created).unwrap_or_default();
+        let remaining = (expiry - age).max(time::Duration::ZERO);
 
-        Ok(true)
+        Some(match self.idle_timeout {
+            Some(idle_timeout) => remaining.min(idle_timeout),
+            None => remaining,
+        })
     }
 }
 
+/// Truncate `s` to at most `max_len` bytes, on a char boundary.
+fn truncate(s: &str, max_len: usize) -> &str {
+    let mut len = s.len().min(max_len);
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    &s[..len]
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct SessionData {
-    secret: SessionSecret,
-    created: SystemTime,
+    pub secret_hash: SessionSecretHash,
+    pub created: SystemTime,
+    /// Client IP at login, if known.
+    pub client_ip: Option<IpAddr>,
+    /// User agent at login, if given.
+    pub user_agent: Option<String>,
+    /// When the session was last used, updated at most every
+    /// [`SessionManager::LAST_USED_INTERVAL`].
+    pub last_used: SystemTime,
+    /// `None` for sessions created before this was recorded.
+    pub login_method: Option<LoginMethod>,
+}
+
+impl SessionData {
+    pub fn info(&self, id: SessionId) -> SessionInfo {
+        SessionInfo {
+            id,
+            created: self.created,
+            client_ip: self.client_ip,
+            user_agent: self.user_agent.clone(),
+            last_used: self.last_used,
+            login_method: self.login_method,
+        }
+    }
+
+    fn is_expired(&self, lifetime: SessionLifetime) -> bool {
+        lifetime.is_expired(self.created, self.last_used)
+    }
+}
+
+/// Details of a stored session, without its secret.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionInfo {
+    pub id: SessionId,
+    pub created: SystemTime,
+    pub client_ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    pub last_used: SystemTime,
+    pub login_method: Option<LoginMethod>,
+}
+
+impl SessionInfo {
+    /// When the session expires if it isn't used again, or `None` if it never expires on the
+    /// server.
+    pub fn expires(
+        &self,
+        expiry: SessionExpiry,
+        idle_timeout: Option<time::Duration>,
+    ) -> Option<SystemTime> {
+        let expires = match expiry {
+            SessionExpiry::Session => None,
+            SessionExpiry::Duration(expiry) => Some(self.created + expiry.unsigned_abs()),
+        };
+        let idle_expires =
+            idle_timeout.map(|idle_timeout| self.last_used + idle_timeout.unsigned_abs());
+
+        match (expires, idle_expires) {
+            (Some(expires), Some(idle_expires)) => Some(expires.min(idle_expires)),
+            (expires, idle_expires) => expires.or(idle_expires),
+        }
+    }
+}
+
+/// How the user logged in to create a session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum LoginMethod {
+    Password,
+    PasswordTotp,
+}
+
+impl fmt::Display for LoginMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Password => write!(f, "password"),
+            Self::PasswordTotp => write!(f, "password+totp"),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -119,8 +529,13 @@ impl SessionSecret {
         Self(buf)
     }
 
-    pub fn verify(&self, other: &Self) -> bool {
-        self.0.ct_eq(&other.0).into()
+    pub fn hash(&self) -> SessionSecretHash {
+        SessionSecretHash(Sha256::digest(&self.0).into())
+    }
+
+    /// Check the secret matches a stored hash, in constant time.
+    pub fn verify(&self, hash: &SessionSecretHash) -> bool {
+        self.hash().0.ct_eq(&hash.0).into()
     }
 }
 
@@ -129,3 +544,26 @@ impl fmt::Debug for SessionSecret {
         f.debug_tuple("SessionSecret").finish_non_exhaustive()
     }
 }
+
+/// SHA-256 hash of a [`SessionSecret`], stored instead of the secret so that reading the
+/// datastore isn't enough to forge session tokens.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct SessionSecretHash([u8; 32]);
+
+impl SessionSecretHash {
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<[u8; 32]> for SessionSecretHash {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl fmt::Debug for SessionSecretHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SessionSecretHash").finish_non_exhaustive()
+    }
+}
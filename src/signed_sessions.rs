@@ -0,0 +1,136 @@
+//! Stateless sessions, where the session cookie carries the session itself, signed with a server
+//! key, instead of referring to a session stored in the datastore.
+
+use std::{fmt, str::FromStr, sync::Arc, time::SystemTime};
+
+use base64ct::{Base64, Base64UrlUnpadded, Encoding};
+use bincode::Options;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use crate::sessions::{LoginMethod, SessionId};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Keys for signing stateless session cookies, in order from oldest to newest.
+///
+/// New sessions are signed with the newest key, while sessions signed with any of the keys are
+/// accepted. To rotate keys, add a new key, and remove the old key once sessions signed with it
+/// have expired (or immediately, to log them out).
+#[derive(Clone)]
+pub struct SessionKeys(Arc<[Zeroizing<Vec<u8>>]>);
+
+impl SessionKeys {
+    const MIN_SIZE: usize = 32; // 256 bits
+
+    fn signing_key(&self) -> &[u8] {
+        self.0.last().expect("there is always at least one key")
+    }
+}
+
+impl FromStr for SessionKeys {
+    type Err = SessionKeysError;
+
+    /// Parse base64 encoded keys, one per line, ignoring blank lines and `#` comments.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let keys = s
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(line_number, line)| {
+                let key = Zeroizing::new(
+                    Base64::decode_vec(line)
+                        .map_err(|e| SessionKeysError::Base64Error(line_number, e))?,
+                );
+                if key.len() < Self::MIN_SIZE {
+                    return Err(SessionKeysError::TooShort(line_number));
+                }
+
+                Ok(key)
+            })
+            .collect::<Result<Arc<[_]>, _>>()?;
+
+        if keys.is_empty() {
+            return Err(SessionKeysError::Empty);
+        }
+
+        Ok(Self(keys))
+    }
+}
+
+impl fmt::Debug for SessionKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SessionKeys").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SessionKeysError {
+    #[error("line {0}: invalid base64: {1}")]
+    Base64Error(usize, base64ct::Error),
+    #[error("line {0}: key must be at least 256 bits")]
+    TooShort(usize),
+    #[error("no keys found")]
+    Empty,
+}
+
+/// A session carried by a signed token.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct SignedSession {
+    pub id: SessionId,
+    pub created: SystemTime,
+    /// When the session expires regardless of use, from the session expiry when it was created.
+    pub expires: Option<SystemTime>,
+    /// When the session was last renewed, for the idle timeout.
+    pub last_used: SystemTime,
+    pub login_method: LoginMethod,
+}
+
+impl SignedSession {
+    /// Separates signatures of session tokens from anything else signed with the same key.
+    const CONTEXT: &[u8] = b"dumb-auth signed session v1\0";
+    const TAG_SIZE: usize = 32;
+
+    /// Sign the session with the newest key, returning the token.
+    pub fn sign(&self, keys: &SessionKeys) -> String {
+        let mut bytes = Self::bincode()
+            .serialize(self)
+            .expect("signed session should always be serializable");
+        let tag = Self::mac(keys.signing_key(), &bytes)
+            .finalize()
+            .into_bytes();
+        bytes.extend_from_slice(&tag);
+
+        Base64UrlUnpadded::encode_string(&bytes)
+    }
+
+    /// Verify the token was signed with one of the keys, returning the session and whether it was
+    /// signed with the newest key.
+    pub fn verify(token: &str, keys: &SessionKeys) -> Option<(Self, bool)> {
+        let bytes = Base64UrlUnpadded::decode_vec(token).ok()?;
+        let (payload, tag) = bytes.split_at(bytes.len().checked_sub(Self::TAG_SIZE)?);
+
+        let key_index = keys
+            .0
+            .iter()
+            .position(|key| Self::mac(key, payload).verify_slice(tag).is_ok())?;
+        let session = Self::bincode().deserialize(payload).ok()?;
+
+        Some((session, key_index == keys.0.len() - 1))
+    }
+
+    fn mac(key: &[u8], payload: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key size");
+        mac.update(Self::CONTEXT);
+        mac.update(payload);
+        mac
+    }
+
+    fn bincode() -> impl bincode::Options {
+        bincode::DefaultOptions::new()
+    }
+}
@@ -0,0 +1,72 @@
+//! Runs the integration tests against a minimal [`SessionStore`] defined outside the crate.
+
+use std::{collections::BTreeMap, sync::Mutex, time::SystemTime};
+
+use dumb_auth::{
+    Datastore, DatastoreError, SessionData, SessionFilter, SessionId, SessionInfo, SessionStore,
+};
+
+#[derive(Default)]
+struct BTreeMapStore {
+    sessions: Mutex<BTreeMap<u64, SessionData>>,
+    next_id: Mutex<u64>,
+}
+
+impl SessionStore for BTreeMapStore {
+    async fn create_session(&self, data: SessionData) -> Result<SessionId, DatastoreError> {
+        let mut next_id = self.next_id.lock().unwrap();
+        let id = *next_id;
+        *next_id += 1;
+        self.sessions.lock().unwrap().insert(id, data);
+        Ok(SessionId(id))
+    }
+
+    async fn read_session(&self, id: SessionId) -> Result<Option<SessionData>, DatastoreError> {
+        Ok(self.sessions.lock().unwrap().get(&id.0).cloned())
+    }
+
+    async fn touch_session(
+        &self,
+        id: SessionId,
+        last_used: SystemTime,
+    ) -> Result<bool, DatastoreError> {
+        Ok(match self.sessions.lock().unwrap().get_mut(&id.0) {
+            Some(data) => {
+                data.last_used = last_used;
+                true
+            }
+            None => false,
+        })
+    }
+
+    async fn count_sessions(&self) -> Result<u64, DatastoreError> {
+        Ok(self.sessions.lock().unwrap().len() as u64)
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<SessionInfo>, DatastoreError> {
+        Ok(self
+            .sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, data)| data.info(SessionId(*id)))
+            .collect())
+    }
+
+    async fn delete_session(&self, id: SessionId) -> Result<bool, DatastoreError> {
+        Ok(self.sessions.lock().unwrap().remove(&id.0).is_some())
+    }
+
+    async fn delete_sessions(&self, filter: SessionFilter) -> Result<u64, DatastoreError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let before = sessions.len();
+        sessions.retain(|id, data| !filter(SessionId(*id), data));
+        Ok((before - sessions.len()) as u64)
+    }
+}
+
+fn create_datastore() -> (Datastore, ()) {
+    (BTreeMapStore::default().into(), ())
+}
+
+mod integration;
@@ -0,0 +1,7 @@
+use dumb_auth::Datastore;
+
+fn create_datastore() -> (Datastore, ()) {
+    (Datastore::new_in_memory(), ())
+}
+
+mod integration;
@@ -0,0 +1,152 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+
+use dumb_auth::{AppConfig, AuthConfig, LoginForm, Password, SessionExpiry, AUDIT_TARGET};
+use reqwest::{header, Method, StatusCode};
+use tracing::{subscriber::DefaultGuard, Level};
+use tracing_subscriber::{filter::Targets, fmt::MakeWriter, layer::SubscriberExt, Layer};
+
+use super::{Sut, ORIGINAL_URI, PASSWORD};
+
+const USER_AGENT: &str = "audit-test";
+
+/// Collects audit events as JSON lines.
+#[derive(Clone, Default)]
+struct AuditLog(Arc<Mutex<Vec<u8>>>);
+
+impl AuditLog {
+    /// Capture audit events on this thread (and so the app, in a single threaded test).
+    fn capture() -> (Self, DefaultGuard) {
+        let log = Self::default();
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .flatten_event(true)
+                .with_writer(log.clone())
+                .with_filter(Targets::new().with_target(AUDIT_TARGET, Level::INFO)),
+        );
+
+        (log, tracing::subscriber::set_default(subscriber))
+    }
+
+    fn events(&self) -> Vec<String> {
+        String::from_utf8(self.0.lock().unwrap().clone())
+            .unwrap()
+            .lines()
+            .map(Into::into)
+            .collect()
+    }
+
+    fn find(&self, event: &str) -> Vec<String> {
+        let field = format!("\"event\":\"{}\"", event);
+        self.events()
+            .into_iter()
+            .filter(|line| line.contains(&field))
+            .collect()
+    }
+}
+
+impl io::Write for AuditLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for AuditLog {
+    type Writer = Self;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+async fn login(sut: &Sut, password: &str) -> StatusCode {
+    sut.request(Method::POST, "/auth/login?redirect_to=/original")
+        .header(header::USER_AGENT, USER_AGENT)
+        .json(&LoginForm {
+            password: password.into(),
+            totp: None,
+        })
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn records_denied_auth_requests() {
+    let (log, _guard) = AuditLog::capture();
+    let sut = Sut::default().await;
+
+    let res = sut
+        .request(Method::GET, "/auth_request")
+        .header("X-Original-URI", ORIGINAL_URI)
+        .header(header::USER_AGENT, USER_AGENT)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let events = log.find("auth_denied");
+    assert_eq!(events.len(), 1, "{:?}", log.events());
+    assert!(events[0].contains("\"reason\":\"invalid\""));
+    assert!(events[0].contains(&format!("\"user_agent\":\"{}\"", USER_AGENT)));
+    assert!(events[0].contains("\"original_uri\":\"/original?uri&query=param\""));
+}
+
+#[tokio::test]
+async fn records_logins_and_logouts() {
+    let (log, _guard) = AuditLog::capture();
+    let sut = Sut::default().await;
+
+    assert_eq!(login(&sut, "invalid").await, StatusCode::UNAUTHORIZED);
+    let events = log.find("login_failed");
+    assert_eq!(events.len(), 1, "{:?}", log.events());
+    assert!(events[0].contains("\"reason\":\"invalid_password\""));
+    assert!(events[0].contains("\"original_uri\":\"/original\""));
+    assert!(log.find("session_created").is_empty());
+
+    assert_eq!(login(&sut, PASSWORD).await, StatusCode::OK);
+    assert_eq!(log.find("session_created").len(), 1);
+    let events = log.find("login");
+    assert_eq!(events.len(), 1, "{:?}", log.events());
+    assert!(events[0].contains("\"session_id\":"));
+    assert!(events[0].contains(&format!("\"user_agent\":\"{}\"", USER_AGENT)));
+
+    let res = sut
+        .request(Method::POST, "/auth/logout")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    assert_eq!(log.find("logout").len(), 1, "{:?}", log.events());
+    assert_eq!(log.find("session_revoked").len(), 1);
+}
+
+#[tokio::test]
+async fn records_expired_sessions() {
+    let (log, _guard) = AuditLog::capture();
+    let sut = Sut::with(|config: &mut AppConfig| config.session_sweep_interval = None).await;
+
+    assert_eq!(login(&sut, PASSWORD).await, StatusCode::OK);
+
+    let mut auth_config = AuthConfig::default(Password::Plain(PASSWORD.into()));
+    auth_config.session_expiry = SessionExpiry::Duration(time::Duration::ZERO);
+    sut.app_handle.set_auth_config(auth_config);
+
+    let res = sut
+        .request(Method::GET, "/auth_request")
+        .header("X-Original-URI", ORIGINAL_URI)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(log.find("session_expired").len(), 1, "{:?}", log.events());
+}
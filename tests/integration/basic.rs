@@ -1,4 +1,4 @@
-use dumb_auth::AppConfig;
+use dumb_auth::{AppConfig, IdentityHeaders};
 use reqwest::{
     header::{self, HeaderValue},
     Method, StatusCode,
@@ -9,6 +9,7 @@ use super::{Sut, ORIGINAL_URI, PASSWORD};
 fn configure(config: &mut AppConfig) {
     config.auth_config.allow_basic = true;
     config.auth_config.allow_session = true;
+    config.auth_config.identity_headers = Some(IdentityHeaders::default());
 }
 
 #[tokio::test]
@@ -86,4 +87,48 @@ async fn returns_200_with_correct_password() {
 
     assert_eq!(res.status(), StatusCode::OK);
     assert_eq!(res.headers().get(header::WWW_AUTHENTICATE), None);
+    assert_eq!(res.headers().get("X-Auth-Method").unwrap(), "basic");
+    assert_eq!(res.headers().get("X-Auth-User").unwrap(), "user");
+    assert_eq!(res.headers().get("X-Auth-Session-Id"), None);
+}
+
+#[tokio::test]
+async fn omits_identity_headers_by_default() {
+    let res = Sut::with(|config| config.auth_config.allow_basic = true)
+        .await
+        .request(Method::GET, "/auth_request")
+        .header("X-Original-URI", ORIGINAL_URI)
+        .basic_auth("user", Some(PASSWORD))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers().get("X-Auth-Method"), None);
+    assert_eq!(res.headers().get("X-Auth-User"), None);
+}
+
+#[tokio::test]
+async fn uses_configured_identity_header_names() {
+    let res = Sut::with(|config| {
+        configure(config);
+        config.auth_config.identity_headers = Some(IdentityHeaders {
+            method: "Remote-Auth-Method".parse().unwrap(),
+            session_id: "Remote-Session".parse().unwrap(),
+            user: "Remote-User".parse().unwrap(),
+        });
+    })
+    .await
+    .request(Method::GET, "/auth_request")
+    .header("X-Original-URI", ORIGINAL_URI)
+    .basic_auth("user", Some(PASSWORD))
+    .send()
+    .await
+    .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers().get("Remote-Auth-Method").unwrap(), "basic");
+    assert_eq!(res.headers().get("Remote-User").unwrap(), "user");
+    assert_eq!(res.headers().get("X-Auth-Method"), None);
+    assert_eq!(res.headers().get("X-Auth-User"), None);
 }
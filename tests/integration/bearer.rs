@@ -1,4 +1,4 @@
-use dumb_auth::AppConfig;
+use dumb_auth::{AppConfig, IdentityHeaders};
 use reqwest::{
     header::{self, HeaderValue},
     Method, StatusCode,
@@ -9,6 +9,7 @@ use super::{Sut, ORIGINAL_URI, PASSWORD};
 fn configure(config: &mut AppConfig) {
     config.auth_config.allow_bearer = true;
     config.auth_config.allow_session = false;
+    config.auth_config.identity_headers = Some(IdentityHeaders::default());
 }
 
 #[tokio::test]
@@ -67,4 +68,6 @@ async fn returns_200_with_correct_password() {
 
     assert_eq!(res.status(), StatusCode::OK);
     assert_eq!(res.headers().get(header::WWW_AUTHENTICATE), None);
+    assert_eq!(res.headers().get("X-Auth-Method").unwrap(), "bearer");
+    assert_eq!(res.headers().get("X-Auth-User"), None);
 }
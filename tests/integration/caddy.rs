@@ -0,0 +1,72 @@
+use dumb_auth::{AppConfig, LoginForm};
+use reqwest::{header, Method, RequestBuilder, StatusCode};
+
+use super::{Sut, ORIGINAL_URI, PASSWORD};
+
+const ORIGINAL_URL_ENCODED: &str = "http%3A%2F%2Fapp.example.com%2Foriginal%3Furi%26query%3Dparam";
+
+/// Caddy's `forward_auth` rewrites the request to `GET /forward_auth`, keeping the original
+/// headers and adding `X-Forwarded-*`.
+fn forward_auth(sut: &Sut) -> RequestBuilder {
+    sut.request(Method::GET, "/forward_auth")
+        .header("X-Forwarded-For", "192.0.2.1")
+        .header("X-Forwarded-Host", "app.example.com")
+        .header("X-Forwarded-Method", "POST")
+        .header("X-Forwarded-Proto", "http")
+        .header("X-Forwarded-Uri", ORIGINAL_URI)
+}
+
+#[tokio::test]
+async fn redirects_browser_to_login() {
+    let sut = Sut::default().await;
+
+    let res = forward_auth(&sut)
+        .header(header::ACCEPT, "text/html,application/xhtml+xml")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::FOUND);
+    assert_eq!(
+        res.headers().get(header::LOCATION).unwrap(),
+        &format!("/auth/login?redirect_to={}", ORIGINAL_URL_ENCODED)
+    );
+}
+
+#[tokio::test]
+async fn returns_www_authenticate_for_basic() {
+    let sut = Sut::with(|config: &mut AppConfig| config.auth_config.allow_basic = true).await;
+
+    let res = forward_auth(&sut).send().await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert!(res.headers().contains_key(header::WWW_AUTHENTICATE));
+
+    let res = forward_auth(&sut)
+        .basic_auth("user", Some(PASSWORD))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn returns_200_with_session() {
+    let sut = Sut::default().await;
+
+    let res = sut
+        .request(Method::POST, "/auth/login")
+        .json(&LoginForm {
+            password: PASSWORD.into(),
+            totp: None,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = forward_auth(&sut).send().await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+}
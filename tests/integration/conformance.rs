@@ -0,0 +1,7 @@
+use dumb_auth::check_datastore_conformance;
+
+#[tokio::test]
+async fn datastore_conforms() {
+    let (datastore, _dir) = crate::create_datastore();
+    check_datastore_conformance(datastore).await;
+}
@@ -0,0 +1,123 @@
+use dumb_auth::{AppConfig, LoginForm};
+use reqwest::{header, Method, StatusCode};
+
+use super::{Sut, ORIGINAL_URI, ORIGINAL_URI_ENCODED, PASSWORD};
+
+async fn login(sut: &Sut) {
+    let res = sut
+        .request(Method::POST, "/auth/login")
+        .json(&LoginForm {
+            password: PASSWORD.into(),
+            totp: None,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn redirects_browser_to_login_with_original_uri() {
+    let res = Sut::default()
+        .await
+        .request(Method::GET, &format!("/ext_authz{}", ORIGINAL_URI))
+        .header(header::ACCEPT, "text/html")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::FOUND);
+    assert_eq!(
+        res.headers().get(header::LOCATION).unwrap(),
+        &format!("/auth/login?redirect_to={}", ORIGINAL_URI_ENCODED)
+    );
+}
+
+#[tokio::test]
+async fn redirects_browser_to_login_for_root() {
+    let sut = Sut::default().await;
+
+    for path in ["/ext_authz", "/ext_authz/"] {
+        let res = sut
+            .request(Method::GET, path)
+            .header(header::ACCEPT, "text/html")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::FOUND);
+        assert_eq!(
+            res.headers().get(header::LOCATION).unwrap(),
+            "/auth/login?redirect_to=%2F"
+        );
+    }
+}
+
+#[tokio::test]
+async fn returns_401_when_non_browser() {
+    let res = Sut::default()
+        .await
+        .request(Method::POST, &format!("/ext_authz{}", ORIGINAL_URI))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(res.headers().get(header::LOCATION), None);
+}
+
+#[tokio::test]
+async fn returns_200_with_session_for_any_method() {
+    let sut = Sut::default().await;
+    login(&sut).await;
+
+    for method in [Method::GET, Method::POST, Method::DELETE] {
+        let res = sut
+            .request(method, &format!("/ext_authz{}", ORIGINAL_URI))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn returns_200_with_basic_auth() {
+    let sut = Sut::with(|config| config.auth_config.allow_basic = true).await;
+
+    let res = sut
+        .request(Method::GET, &format!("/ext_authz{}", ORIGINAL_URI))
+        .basic_auth("user", Some(PASSWORD))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn uses_configured_prefix() {
+    let sut = Sut::with(|config: &mut AppConfig| config.ext_authz_prefix = "/envoy".into()).await;
+
+    let res = sut
+        .request(Method::GET, &format!("/envoy{}", ORIGINAL_URI))
+        .header(header::ACCEPT, "text/html")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::FOUND);
+    assert_eq!(
+        res.headers().get(header::LOCATION).unwrap(),
+        &format!("/auth/login?redirect_to={}", ORIGINAL_URI_ENCODED)
+    );
+
+    let res = sut
+        .request(Method::GET, &format!("/ext_authz{}", ORIGINAL_URI))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,118 @@
+use dumb_auth::{AppConfig, LoginForm, LoginResponse};
+use reqwest::{header, Method, RequestBuilder, StatusCode};
+
+use super::{Sut, ORIGINAL_URI, PASSWORD};
+
+const FORWARDED_HOST: &str = "app.example.com";
+const ORIGINAL_URL_ENCODED: &str = "https%3A%2F%2Fapp.example.com%2Foriginal%3Furi%26query%3Dparam";
+
+fn forward_auth(sut: &Sut) -> RequestBuilder {
+    sut.request(Method::GET, "/forward_auth")
+        .header("X-Forwarded-Method", "GET")
+        .header("X-Forwarded-Proto", "https")
+        .header("X-Forwarded-Host", FORWARDED_HOST)
+        .header("X-Forwarded-Uri", ORIGINAL_URI)
+}
+
+#[tokio::test]
+async fn returns_400_without_forwarded_uri() {
+    let res = Sut::default()
+        .await
+        .request(Method::GET, "/forward_auth")
+        .header("X-Forwarded-Host", FORWARDED_HOST)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn redirects_browser_to_login_with_original_url() {
+    let sut = Sut::default().await;
+
+    let res = forward_auth(&sut)
+        .header(header::ACCEPT, "text/html")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::FOUND);
+    assert_eq!(
+        res.headers().get(header::LOCATION).unwrap(),
+        &format!("/auth/login?redirect_to={}", ORIGINAL_URL_ENCODED)
+    );
+}
+
+#[tokio::test]
+async fn returns_401_when_non_browser() {
+    let sut = Sut::default().await;
+
+    let res = forward_auth(&sut).send().await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(res.headers().get(header::LOCATION), None);
+}
+
+#[tokio::test]
+async fn returns_401_with_www_authenticate_for_basic() {
+    let sut = Sut::with(|config: &mut AppConfig| config.auth_config.allow_basic = true).await;
+
+    let res = forward_auth(&sut)
+        .basic_auth("user", Some("invalid"))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert!(res.headers().contains_key(header::WWW_AUTHENTICATE));
+}
+
+#[tokio::test]
+async fn returns_200_with_session() {
+    let sut = Sut::default().await;
+
+    let res = sut
+        .request(Method::POST, "/auth/login")
+        .json(&LoginForm {
+            password: PASSWORD.into(),
+            totp: None,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = forward_auth(&sut)
+        .header(header::ACCEPT, "text/html")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn login_allows_redirect_to_forwarded_host() {
+    let sut = Sut::default().await;
+
+    let res = sut
+        .request(
+            Method::POST,
+            &format!("/auth/login?redirect_to={}", ORIGINAL_URL_ENCODED),
+        )
+        .header(header::HOST, FORWARDED_HOST)
+        .json(&LoginForm {
+            password: PASSWORD.into(),
+            totp: None,
+        })
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.json::<LoginResponse>().await.unwrap().redirect_to,
+        format!("https://{}{}", FORWARDED_HOST, ORIGINAL_URI)
+    );
+}
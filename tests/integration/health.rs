@@ -0,0 +1,39 @@
+use reqwest::{Method, StatusCode};
+
+use super::Sut;
+
+#[tokio::test]
+async fn health_returns_200() {
+    let res = Sut::default()
+        .await
+        .request(Method::GET, "/health")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn ready_returns_200_when_datastore_usable() {
+    let res = Sut::default()
+        .await
+        .request(Method::GET, "/ready")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn health_does_not_require_auth() {
+    let res = Sut::with(|config| config.auth_config.allow_session = false)
+        .await
+        .request(Method::GET, "/health")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+}
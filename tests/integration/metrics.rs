@@ -0,0 +1,93 @@
+use dumb_auth::{AppConfig, LoginForm};
+use reqwest::{Method, StatusCode};
+use tokio::net::TcpListener;
+
+use super::{Sut, ORIGINAL_URI, PASSWORD};
+
+fn configure(config: &mut AppConfig) {
+    config.auth_config.allow_basic = true;
+}
+
+async fn scrape(sut: &Sut) -> String {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let url = format!("http://{}/metrics", listener.local_addr().unwrap());
+    let app = sut.app_handle.metrics_app();
+    let server = tokio::spawn(async {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    let body = reqwest::get(url).await.unwrap().text().await.unwrap();
+    server.abort();
+    body
+}
+
+async fn auth_request(sut: &Sut, password: Option<&str>) -> StatusCode {
+    let mut req = sut
+        .request(Method::GET, "/auth_request")
+        .header("X-Original-URI", ORIGINAL_URI);
+    if let Some(password) = password {
+        req = req.basic_auth("user", Some(password));
+    }
+
+    req.send().await.unwrap().status()
+}
+
+async fn login(sut: &Sut, password: &str) -> StatusCode {
+    sut.request(Method::POST, "/auth/login")
+        .json(&LoginForm {
+            password: password.into(),
+            totp: None,
+        })
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn counts_auth_requests() {
+    let sut = Sut::with(configure).await;
+
+    assert_eq!(auth_request(&sut, Some(PASSWORD)).await, StatusCode::OK);
+    assert_eq!(
+        auth_request(&sut, Some("invalid")).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(auth_request(&sut, None).await, StatusCode::UNAUTHORIZED);
+
+    let metrics = scrape(&sut).await;
+    assert!(metrics.contains("dumb_auth_auth_requests_total{result=\"valid\"} 1\n"));
+    assert!(metrics.contains("dumb_auth_auth_requests_total{result=\"invalid\"} 2\n"));
+    assert!(metrics
+        .contains("dumb_auth_auth_method_checks_total{method=\"basic\",result=\"valid\"} 1\n"));
+    assert!(metrics
+        .contains("dumb_auth_auth_method_checks_total{method=\"basic\",result=\"invalid\"} 2\n"));
+    assert!(metrics
+        .contains("dumb_auth_auth_method_checks_total{method=\"session\",result=\"invalid\"} 2\n"));
+}
+
+#[tokio::test]
+async fn counts_logins_and_sessions() {
+    let sut = Sut::default().await;
+
+    assert_eq!(login(&sut, "invalid").await, StatusCode::UNAUTHORIZED);
+    assert_eq!(login(&sut, PASSWORD).await, StatusCode::OK);
+    assert_eq!(login(&sut, PASSWORD).await, StatusCode::OK);
+
+    let metrics = scrape(&sut).await;
+    assert!(metrics.contains("dumb_auth_logins_total{result=\"invalid_password\"} 1\n"));
+    assert!(metrics.contains("dumb_auth_logins_total{result=\"valid\"} 2\n"));
+    assert!(metrics.contains("dumb_auth_sessions 2\n"));
+}
+
+#[tokio::test]
+async fn metrics_not_served_by_app() {
+    let res = Sut::default()
+        .await
+        .request(Method::GET, "/metrics")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
@@ -1,22 +1,37 @@
-use std::sync::Arc;
+use std::{any::Any, sync::Arc};
 
-use dumb_auth::{AppConfig, AuthConfig, Password};
-use reqwest::{cookie, Client, Method, RequestBuilder, Url};
+use dumb_auth::{AppConfig, AppHandle, AuthConfig, Password};
+use reqwest::{cookie, redirect, Client, Method, RequestBuilder, Url};
 use tokio::{net::TcpListener, task::JoinHandle};
 
+mod audit;
 mod basic;
 mod bearer;
+mod caddy;
+mod conformance;
+mod ext_authz;
+mod forward_auth;
+mod health;
+mod metrics;
+mod rate_limit;
+mod reload;
 mod session;
+mod shutdown;
+mod signed_session;
+mod totp;
 
 pub const PASSWORD: &str = "hunter2";
 pub const ORIGINAL_URI: &str = "/original?uri&query=param";
 pub const ORIGINAL_URI_ENCODED: &str = "%2Foriginal%3Furi%26query%3Dparam";
 
 pub struct Sut {
+    pub app_handle: AppHandle,
     base_url: Url,
     cookies: Arc<cookie::Jar>,
     client: Client,
     handle: JoinHandle<()>,
+    /// Keeps the datastore's files until the test ends.
+    _datastore_guard: Box<dyn Any + Send + Sync>,
 }
 
 impl Sut {
@@ -40,21 +55,23 @@ impl Sut {
         let cookies = Arc::new(cookie::Jar::default());
         let client = Client::builder()
             .cookie_provider(cookies.clone())
+            .redirect(redirect::Policy::none())
             .build()
             .unwrap();
 
+        let (datastore, datastore_guard) = crate::create_datastore();
+        let (app, app_handle) = dumb_auth::app_with_handle(config, datastore);
         let handle = tokio::spawn(async {
-            let (datastore, _) = super::create_datastore();
-            axum::serve(listener, dumb_auth::app(config, datastore))
-                .await
-                .unwrap();
+            axum::serve(listener, app).await.unwrap();
         });
 
         Self {
+            app_handle,
             base_url,
             cookies,
             client,
             handle,
+            _datastore_guard: Box::new(datastore_guard),
         }
     }
 
@@ -0,0 +1,178 @@
+use std::time::Duration;
+
+use dumb_auth::{AppConfig, LoginForm, RateLimitConfig};
+use reqwest::{header, Method, StatusCode};
+
+use super::{Sut, ORIGINAL_URI, PASSWORD};
+
+const CLIENT_IP_HEADER: &str = "X-Real-IP";
+const CLIENT_IP: &str = "192.0.2.1";
+const OTHER_CLIENT_IP: &str = "192.0.2.2";
+
+fn configure(config: &mut AppConfig) {
+    config.auth_config.allow_basic = true;
+    config.rate_limit = Some(RateLimitConfig {
+        max_attempts: 2,
+        lockout: Duration::from_secs(60),
+        max_lockout: Duration::from_secs(60 * 60),
+    });
+    config.client_ip_header = Some(CLIENT_IP_HEADER.parse().unwrap());
+}
+
+async fn login(sut: &Sut, client_ip: &str, password: &str) -> reqwest::Response {
+    sut.request(Method::POST, "/auth/login")
+        .header(CLIENT_IP_HEADER, client_ip)
+        .json(&LoginForm {
+            password: password.into(),
+            totp: None,
+        })
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn basic_auth(sut: &Sut, client_ip: &str, password: &str) -> reqwest::Response {
+    sut.request(Method::GET, "/auth_request")
+        .header("X-Original-URI", ORIGINAL_URI)
+        .header(CLIENT_IP_HEADER, client_ip)
+        .basic_auth("user", Some(password))
+        .send()
+        .await
+        .unwrap()
+}
+
+fn assert_locked_out(res: &reqwest::Response) {
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers().get(header::RETRY_AFTER).unwrap(), "60");
+}
+
+#[tokio::test]
+async fn login_locks_out_after_failed_attempts() {
+    let sut = Sut::with(configure).await;
+
+    assert_eq!(
+        login(&sut, CLIENT_IP, "invalid").await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        login(&sut, CLIENT_IP, "invalid").await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    // Locked out even with the correct password
+    assert_locked_out(&login(&sut, CLIENT_IP, PASSWORD).await);
+    assert_locked_out(&basic_auth(&sut, CLIENT_IP, PASSWORD).await);
+
+    // Other clients aren't affected
+    assert_eq!(
+        login(&sut, OTHER_CLIENT_IP, PASSWORD).await.status(),
+        StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn auth_request_locks_out_after_failed_attempts() {
+    let sut = Sut::with(configure).await;
+
+    assert_eq!(
+        basic_auth(&sut, CLIENT_IP, "invalid").await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        basic_auth(&sut, CLIENT_IP, "invalid").await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    assert_locked_out(&basic_auth(&sut, CLIENT_IP, PASSWORD).await);
+    assert_locked_out(&login(&sut, CLIENT_IP, PASSWORD).await);
+}
+
+#[tokio::test]
+async fn successful_attempt_resets_failures() {
+    let sut = Sut::with(configure).await;
+
+    for _ in 0..3 {
+        assert_eq!(
+            login(&sut, CLIENT_IP, "invalid").await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            login(&sut, CLIENT_IP, PASSWORD).await.status(),
+            StatusCode::OK
+        );
+    }
+}
+
+#[tokio::test]
+async fn requests_without_password_are_not_limited() {
+    let sut = Sut::with(configure).await;
+
+    for _ in 0..3 {
+        let res = sut
+            .request(Method::GET, "/auth_request")
+            .header("X-Original-URI", ORIGINAL_URI)
+            .header(CLIENT_IP_HEADER, CLIENT_IP)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+}
+
+#[tokio::test]
+async fn uses_last_forwarded_for_address() {
+    let sut = Sut::with(|config| {
+        configure(config);
+        config.client_ip_header = Some("X-Forwarded-For".parse().unwrap());
+    })
+    .await;
+
+    let login = |client_ips: &'static str| {
+        sut.request(Method::POST, "/auth/login")
+            .header("X-Forwarded-For", client_ips)
+            .json(&LoginForm {
+                password: "invalid".into(),
+                totp: None,
+            })
+            .send()
+    };
+
+    // Spoofed addresses before the proxy's address are ignored
+    login("198.51.100.1, 192.0.2.1").await.unwrap();
+    login("198.51.100.2, 192.0.2.1").await.unwrap();
+
+    assert_locked_out(&login("198.51.100.3, 192.0.2.1").await.unwrap());
+}
+
+#[tokio::test]
+async fn sessions_are_not_limited() {
+    let sut = Sut::with(configure).await;
+
+    assert_eq!(
+        login(&sut, CLIENT_IP, PASSWORD).await.status(),
+        StatusCode::OK
+    );
+    for _ in 0..2 {
+        assert_eq!(
+            login(&sut, CLIENT_IP, "invalid").await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+    assert_locked_out(&login(&sut, CLIENT_IP, PASSWORD).await);
+
+    let res = sut
+        .request(Method::GET, "/auth_request")
+        .header("X-Original-URI", ORIGINAL_URI)
+        .header(CLIENT_IP_HEADER, CLIENT_IP)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // Still valid alongside a password, which isn't checked while locked out
+    assert_eq!(
+        basic_auth(&sut, CLIENT_IP, "invalid").await.status(),
+        StatusCode::OK
+    );
+}
@@ -0,0 +1,82 @@
+use dumb_auth::{AppConfig, AuthConfig, LoginForm, Password, SessionExpiry};
+use reqwest::{Method, StatusCode};
+
+use super::{Sut, ORIGINAL_URI, PASSWORD};
+
+const NEW_PASSWORD: &str = "hunter3";
+
+fn configure(config: &mut AppConfig) {
+    config.auth_config.allow_basic = true;
+}
+
+async fn auth_request(sut: &Sut, password: Option<&str>) -> StatusCode {
+    let mut req = sut
+        .request(Method::GET, "/auth_request")
+        .header("X-Original-URI", ORIGINAL_URI);
+    if let Some(password) = password {
+        req = req.basic_auth("user", Some(password));
+    }
+    req.send().await.unwrap().status()
+}
+
+fn new_auth_config() -> AuthConfig {
+    let mut auth_config = AuthConfig::default(Password::Plain(NEW_PASSWORD.into()));
+    auth_config.allow_basic = true;
+    auth_config
+}
+
+#[tokio::test]
+async fn set_auth_config_changes_password() {
+    let sut = Sut::with(configure).await;
+    assert_eq!(auth_request(&sut, Some(PASSWORD)).await, StatusCode::OK);
+
+    sut.app_handle.set_auth_config(new_auth_config());
+
+    assert_eq!(
+        auth_request(&sut, Some(PASSWORD)).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(auth_request(&sut, Some(NEW_PASSWORD)).await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn set_auth_config_keeps_sessions() {
+    let sut = Sut::with(configure).await;
+
+    let res = sut
+        .request(Method::POST, "/auth/login")
+        .json(&LoginForm {
+            password: PASSWORD.into(),
+            totp: None,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    sut.app_handle.set_auth_config(new_auth_config());
+
+    assert_eq!(auth_request(&sut, None).await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn set_auth_config_applies_session_expiry() {
+    let sut = Sut::with(configure).await;
+
+    let res = sut
+        .request(Method::POST, "/auth/login")
+        .json(&LoginForm {
+            password: PASSWORD.into(),
+            totp: None,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let mut auth_config = new_auth_config();
+    auth_config.session_expiry = SessionExpiry::Duration(time::Duration::ZERO);
+    sut.app_handle.set_auth_config(auth_config);
+
+    assert_eq!(auth_request(&sut, None).await, StatusCode::UNAUTHORIZED);
+}
@@ -1,4 +1,9 @@
-use dumb_auth::{AuthConfig, LoginForm};
+use std::time::{Duration, SystemTime};
+
+use dumb_auth::{
+    AppConfig, AuthConfig, IdentityHeaders, LoginForm, LoginMethod, LoginResponse, Password,
+    SessionExpiry,
+};
 use reqwest::{header, Method, StatusCode};
 
 use super::{Sut, ORIGINAL_URI, ORIGINAL_URI_ENCODED, PASSWORD};
@@ -65,6 +70,7 @@ async fn login_returns_401_with_incorrect_password() {
         .request(Method::POST, "/auth/login")
         .json(&LoginForm {
             password: "invalid".into(),
+            totp: None,
         })
         .send()
         .await
@@ -76,12 +82,16 @@ async fn login_returns_401_with_incorrect_password() {
 
 #[tokio::test]
 async fn login_grants_session_with_correct_password() {
-    let sut = Sut::default().await;
+    let sut = Sut::with(|config: &mut AppConfig| {
+        config.auth_config.identity_headers = Some(IdentityHeaders::default());
+    })
+    .await;
 
     let res = sut
         .request(Method::POST, "/auth/login")
         .json(&LoginForm {
             password: PASSWORD.into(),
+            totp: None,
         })
         .send()
         .await
@@ -90,8 +100,7 @@ async fn login_grants_session_with_correct_password() {
     assert_eq!(res.status(), StatusCode::OK);
     assert!(res
         .cookies()
-        .find(|c| c.name() == AuthConfig::DEFAULT_SESSION_COOKIE_NAME)
-        .is_some());
+        .any(|c| c.name() == AuthConfig::DEFAULT_SESSION_COOKIE_NAME));
 
     let res = sut
         .request(Method::GET, "/auth_request")
@@ -103,4 +112,386 @@ async fn login_grants_session_with_correct_password() {
     assert_eq!(res.status(), StatusCode::OK);
     assert_eq!(res.headers().get(header::LOCATION), None);
     assert_eq!(res.headers().get(header::WWW_AUTHENTICATE), None);
+    assert_eq!(res.headers().get("X-Auth-Method").unwrap(), "session");
+    assert!(res
+        .headers()
+        .get("X-Auth-Session-Id")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .parse::<u64>()
+        .is_ok());
+}
+
+#[tokio::test]
+async fn logout_revokes_session() {
+    let sut = Sut::default().await;
+
+    let res = sut
+        .request(Method::POST, "/auth/login")
+        .json(&LoginForm {
+            password: PASSWORD.into(),
+            totp: None,
+        })
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    let session_token = res
+        .cookies()
+        .find(|c| c.name() == AuthConfig::DEFAULT_SESSION_COOKIE_NAME)
+        .unwrap()
+        .value()
+        .to_string();
+
+    // Only POST, so that other sites can't log the user out with a link or image
+    let res = sut
+        .request(Method::GET, "/auth/logout")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(auth_request_status(&sut).await, StatusCode::OK);
+
+    let res = sut
+        .request(Method::POST, "/auth/logout")
+        .send()
+        .await
+        .unwrap();
+
+    // Redirects to login and clears cookie
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    assert_eq!(res.headers().get(header::LOCATION).unwrap(), "/auth/login");
+
+    let res = sut
+        .request(Method::GET, "/auth_request")
+        .header("X-Original-URI", ORIGINAL_URI)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    // Session is no longer valid even if the cookie is presented again
+    sut.set_cookie(AuthConfig::DEFAULT_SESSION_COOKIE_NAME, &session_token);
+
+    let res = sut
+        .request(Method::GET, "/auth_request")
+        .header("X-Original-URI", ORIGINAL_URI)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+}
+
+async fn login_redirect(sut: &Sut, redirect_to: &str) -> String {
+    let res = sut
+        .request(Method::POST, "/auth/login")
+        .query(&[("redirect_to", redirect_to)])
+        .json(&LoginForm {
+            password: PASSWORD.into(),
+            totp: None,
+        })
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    res.json::<LoginResponse>().await.unwrap().redirect_to
+}
+
+#[tokio::test]
+async fn login_redirects_to_root_by_default() {
+    let sut = Sut::default().await;
+
+    let res = sut
+        .request(Method::POST, "/auth/login")
+        .json(&LoginForm {
+            password: PASSWORD.into(),
+            totp: None,
+        })
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.json::<LoginResponse>().await.unwrap().redirect_to, "/");
+}
+
+#[tokio::test]
+async fn login_allows_relative_redirect() {
+    let sut = Sut::default().await;
+
+    assert_eq!(login_redirect(&sut, ORIGINAL_URI).await, ORIGINAL_URI);
+}
+
+#[tokio::test]
+async fn login_rejects_external_redirect() {
+    let sut = Sut::default().await;
+
+    assert_eq!(login_redirect(&sut, "https://evil.example/").await, "/");
+    assert_eq!(login_redirect(&sut, "//evil.example/").await, "/");
+    assert_eq!(login_redirect(&sut, "/\\evil.example/").await, "/");
+    assert_eq!(login_redirect(&sut, "javascript:alert(1)").await, "/");
+}
+
+#[tokio::test]
+async fn login_allows_same_host_redirect() {
+    let sut = Sut::default().await;
+    let same_host = sut.request(Method::GET, ORIGINAL_URI).build().unwrap();
+    let same_host = same_host.url().as_str();
+
+    assert_eq!(login_redirect(&sut, same_host).await, same_host);
+}
+
+#[tokio::test]
+async fn login_allows_configured_redirect_hosts() {
+    let sut = Sut::with(|config: &mut AppConfig| {
+        config.auth_config.session_cookie_domain = Some("example.com".into());
+        config.auth_config.allowed_redirect_hosts = vec!["other.example".into()];
+    })
+    .await;
+
+    for redirect_to in [
+        "https://example.com/",
+        "https://a.example.com/path?query",
+        "http://other.example/",
+    ] {
+        assert_eq!(login_redirect(&sut, redirect_to).await, redirect_to);
+    }
+
+    for redirect_to in [
+        "https://notexample.com/",
+        "https://example.com.evil.example/",
+        "https://a.other.example/",
+    ] {
+        assert_eq!(login_redirect(&sut, redirect_to).await, "/");
+    }
+}
+
+async fn login(sut: &Sut) {
+    let res = sut
+        .request(Method::POST, "/auth/login")
+        .json(&LoginForm {
+            password: PASSWORD.into(),
+            totp: None,
+        })
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+async fn auth_request_status(sut: &Sut) -> StatusCode {
+    sut.request(Method::GET, "/auth_request")
+        .header("X-Original-URI", ORIGINAL_URI)
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn lists_sessions() {
+    let sut = Sut::default().await;
+    let datastore = sut.app_handle.datastore();
+    assert!(datastore.list_sessions().await.unwrap().is_empty());
+
+    let before = SystemTime::now();
+    login(&sut).await;
+    login(&sut).await;
+
+    let sessions = datastore.list_sessions().await.unwrap();
+    assert_eq!(sessions.len(), 2);
+    assert!(sessions[0].id.0 < sessions[1].id.0);
+    assert!(sessions.iter().all(|session| session.created >= before));
+}
+
+#[tokio::test]
+async fn login_records_session_metadata() {
+    let sut = Sut::default().await;
+
+    let before = SystemTime::now();
+    let res = sut
+        .request(Method::POST, "/auth/login")
+        .header(header::USER_AGENT, "test-agent/1.0")
+        .json(&LoginForm {
+            password: PASSWORD.into(),
+            totp: None,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let sessions = sut.app_handle.datastore().list_sessions().await.unwrap();
+    assert_eq!(sessions[0].user_agent.as_deref(), Some("test-agent/1.0"));
+    assert_eq!(sessions[0].login_method, Some(LoginMethod::Password));
+    assert!(sessions[0].last_used >= before);
+
+    // Recently used sessions aren't updated on every request
+    assert_eq!(auth_request_status(&sut).await, StatusCode::OK);
+    let last_used = sut.app_handle.datastore().list_sessions().await.unwrap()[0].last_used;
+    assert_eq!(last_used, sessions[0].last_used);
+}
+
+#[tokio::test]
+async fn revoked_session_is_rejected() {
+    let sut = Sut::default().await;
+    let datastore = sut.app_handle.datastore();
+
+    login(&sut).await;
+    assert_eq!(auth_request_status(&sut).await, StatusCode::OK);
+
+    let id = datastore.list_sessions().await.unwrap()[0].id;
+    assert!(datastore.revoke_session(id).await.unwrap());
+    assert_eq!(auth_request_status(&sut).await, StatusCode::UNAUTHORIZED);
+
+    // Already revoked
+    assert!(!datastore.revoke_session(id).await.unwrap());
+}
+
+#[tokio::test]
+async fn revokes_sessions_matching_filter() {
+    let sut = Sut::default().await;
+    let datastore = sut.app_handle.datastore();
+
+    login(&sut).await;
+    let first = datastore.list_sessions().await.unwrap()[0].id;
+    login(&sut).await;
+
+    assert_eq!(
+        datastore
+            .revoke_sessions(move |session| session.id == first)
+            .await
+            .unwrap(),
+        1
+    );
+
+    let sessions = datastore.list_sessions().await.unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_ne!(sessions[0].id, first);
+    assert_eq!(auth_request_status(&sut).await, StatusCode::OK);
+
+    assert_eq!(datastore.revoke_sessions(|_| true).await.unwrap(), 1);
+    assert_eq!(auth_request_status(&sut).await, StatusCode::UNAUTHORIZED);
+}
+
+fn configure_idle_timeout(config: &mut AppConfig) {
+    config.auth_config.session_idle_timeout = Some(time::Duration::seconds(2));
+}
+
+#[tokio::test]
+async fn login_sets_cookie_max_age_to_idle_timeout() {
+    let sut = Sut::with(configure_idle_timeout).await;
+
+    let res = sut
+        .request(Method::POST, "/auth/login")
+        .json(&LoginForm {
+            password: PASSWORD.into(),
+            totp: None,
+        })
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    let cookie = res
+        .cookies()
+        .find(|c| c.name() == AuthConfig::DEFAULT_SESSION_COOKIE_NAME)
+        .unwrap();
+    assert_eq!(cookie.max_age(), Some(Duration::from_secs(2)));
+}
+
+#[tokio::test]
+async fn renews_session_when_used() {
+    let sut = Sut::with(configure_idle_timeout).await;
+    login(&sut).await;
+
+    // Renewed after half the idle timeout
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    let res = sut
+        .request(Method::GET, "/auth_request")
+        .header("X-Original-URI", ORIGINAL_URI)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    let cookie = res
+        .cookies()
+        .find(|c| c.name() == AuthConfig::DEFAULT_SESSION_COOKIE_NAME)
+        .unwrap();
+    assert_eq!(cookie.max_age(), Some(Duration::from_secs(2)));
+
+    // Still valid more than the idle timeout after logging in
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(auth_request_status(&sut).await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn rejects_idle_session() {
+    let sut = Sut::default().await;
+    login(&sut).await;
+
+    let mut auth_config = AuthConfig::default(Password::Plain(PASSWORD.into()));
+    auth_config.session_idle_timeout = Some(time::Duration::ZERO);
+    sut.app_handle.set_auth_config(auth_config);
+
+    assert_eq!(auth_request_status(&sut).await, StatusCode::UNAUTHORIZED);
+    assert!(sut
+        .app_handle
+        .datastore()
+        .list_sessions()
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn sweeper_deletes_expired_sessions() {
+    let sut = Sut::with(|config: &mut AppConfig| {
+        config.session_sweep_interval = Some(time::Duration::minutes(10));
+    })
+    .await;
+    let datastore = sut.app_handle.datastore();
+
+    let res = sut
+        .request(Method::POST, "/auth/login")
+        .json(&LoginForm {
+            password: PASSWORD.into(),
+            totp: None,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let mut auth_config = AuthConfig::default(Password::Plain(PASSWORD.into()));
+    auth_config.session_expiry = SessionExpiry::Duration(time::Duration::ZERO);
+    sut.app_handle.set_auth_config(auth_config);
+    assert_eq!(datastore.list_sessions().await.unwrap().len(), 1);
+
+    tokio::spawn(sut.app_handle.session_sweeper().unwrap());
+    tokio::time::advance(Duration::from_secs(10 * 60)).await;
+
+    // The datastore may do the work on another thread, which paused time doesn't wait for
+    let deadline = std::time::Instant::now() + Duration::from_secs(10);
+    while !datastore.list_sessions().await.unwrap().is_empty()
+        && std::time::Instant::now() < deadline
+    {
+        tokio::task::yield_now().await;
+    }
+    assert!(datastore.list_sessions().await.unwrap().is_empty());
+}
+
+#[test]
+fn app_can_be_created_outside_runtime() {
+    let (datastore, _guard) = crate::create_datastore();
+    let config = AppConfig::default(AuthConfig::default(Password::Plain(PASSWORD.into())));
+    let (_, app_handle) = dumb_auth::app_with_handle(config, datastore);
+    assert!(app_handle.session_sweeper().is_some());
 }
@@ -0,0 +1,23 @@
+use std::time::Duration;
+
+use dumb_auth::{AppConfig, AuthConfig, Password};
+use tokio::task;
+
+use super::PASSWORD;
+
+#[tokio::test]
+async fn datastore_closes_when_app_dropped() {
+    let (datastore, _dir) = crate::create_datastore();
+    let closing_event = datastore.closing_event();
+
+    let app = dumb_auth::app(
+        AppConfig::default(AuthConfig::default(Password::Plain(PASSWORD.into()))),
+        datastore,
+    );
+    drop(app);
+
+    let closed = task::spawn_blocking(move || closing_event.wait_timeout(Duration::from_secs(5)))
+        .await
+        .unwrap();
+    assert!(closed);
+}
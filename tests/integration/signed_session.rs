@@ -0,0 +1,174 @@
+use dumb_auth::{
+    AppConfig, AuthConfig, IdentityHeaders, LoginForm, Password, SessionId, SessionKeys,
+};
+use reqwest::{header, Method, Response, StatusCode};
+
+use super::{Sut, ORIGINAL_URI, PASSWORD};
+
+const OLD_KEY: &str = "AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+const NEW_KEY: &str = "AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=";
+const COOKIE_NAME: &str = AuthConfig::DEFAULT_SESSION_COOKIE_NAME;
+
+fn auth_config(keys: &[&str]) -> AuthConfig {
+    let mut auth_config = AuthConfig::default(Password::Plain(PASSWORD.into()));
+    auth_config.session_keys = Some(keys.join("\n").parse::<SessionKeys>().unwrap());
+    auth_config.identity_headers = Some(IdentityHeaders::default());
+    auth_config
+}
+
+async fn sut(keys: &[&str]) -> Sut {
+    Sut::new(AppConfig::default(auth_config(keys))).await
+}
+
+/// Log in, returning the session token.
+async fn login(sut: &Sut) -> String {
+    let res = sut
+        .request(Method::POST, "/auth/login")
+        .json(&LoginForm {
+            password: PASSWORD.into(),
+            totp: None,
+        })
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    session_cookie(&res).unwrap()
+}
+
+async fn auth_request(sut: &Sut) -> Response {
+    sut.request(Method::GET, "/auth_request")
+        .header("X-Original-URI", ORIGINAL_URI)
+        .send()
+        .await
+        .unwrap()
+}
+
+fn session_cookie(res: &Response) -> Option<String> {
+    res.cookies()
+        .find(|c| c.name() == COOKIE_NAME)
+        .map(|c| c.value().to_string())
+}
+
+fn session_id(res: &Response) -> SessionId {
+    SessionId(
+        res.headers()
+            .get("X-Auth-Session-Id")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap(),
+    )
+}
+
+#[tokio::test]
+async fn signed_session_is_not_stored() {
+    let sut = sut(&[NEW_KEY]).await;
+    login(&sut).await;
+
+    let res = auth_request(&sut).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers().get("X-Auth-Method").unwrap(), "session");
+    assert_eq!(session_cookie(&res), None);
+
+    assert!(sut
+        .app_handle
+        .datastore()
+        .list_sessions()
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn signed_session_is_accepted_by_other_instances_with_same_key() {
+    let token = login(&sut(&[NEW_KEY]).await).await;
+
+    let other = sut(&[NEW_KEY]).await;
+    other.set_cookie(COOKIE_NAME, &token);
+    assert_eq!(auth_request(&other).await.status(), StatusCode::OK);
+
+    let other = sut(&[OLD_KEY]).await;
+    other.set_cookie(COOKIE_NAME, &token);
+    assert_eq!(
+        auth_request(&other).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn rejects_tampered_signed_session() {
+    let sut = sut(&[NEW_KEY]).await;
+    let token = login(&sut).await;
+
+    let mut tampered = token.into_bytes();
+    tampered[4] = if tampered[4] == b'A' { b'B' } else { b'A' };
+    sut.set_cookie(COOKIE_NAME, &String::from_utf8(tampered).unwrap());
+
+    assert_eq!(auth_request(&sut).await.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn rotates_session_keys() {
+    let sut = sut(&[OLD_KEY]).await;
+    let old_token = login(&sut).await;
+
+    // Still accepted after adding a new key, and re-signed with the new key
+    sut.app_handle
+        .set_auth_config(auth_config(&[OLD_KEY, NEW_KEY]));
+    let res = auth_request(&sut).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let new_token = session_cookie(&res).unwrap();
+    assert_ne!(new_token, old_token);
+
+    // Only the re-signed session is accepted after removing the old key
+    sut.app_handle.set_auth_config(auth_config(&[NEW_KEY]));
+    assert_eq!(auth_request(&sut).await.status(), StatusCode::OK);
+
+    sut.set_cookie(COOKIE_NAME, &old_token);
+    assert_eq!(auth_request(&sut).await.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn rejects_revoked_signed_session() {
+    let sut = sut(&[NEW_KEY]).await;
+    login(&sut).await;
+
+    let res = auth_request(&sut).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let id = session_id(&res);
+
+    let mut auth_config = auth_config(&[NEW_KEY]);
+    auth_config.revoked_sessions = vec![id];
+    sut.app_handle.set_auth_config(auth_config);
+
+    assert_eq!(auth_request(&sut).await.status(), StatusCode::UNAUTHORIZED);
+
+    // Other sessions are unaffected
+    login(&sut).await;
+    assert_eq!(auth_request(&sut).await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn logout_removes_signed_session_cookie() {
+    let sut = sut(&[NEW_KEY]).await;
+    login(&sut).await;
+
+    let res = sut
+        .request(Method::POST, "/auth/logout")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    assert!(res
+        .headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .any(|value| value
+            .to_str()
+            .unwrap()
+            .starts_with(&format!("{COOKIE_NAME}="))));
+
+    assert_eq!(auth_request(&sut).await.status(), StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,160 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use dumb_auth::{AppConfig, LoginForm, LoginMethod, TotpSecret};
+use reqwest::{Method, StatusCode};
+
+use super::{Sut, ORIGINAL_URI, PASSWORD};
+
+// "12345678901234567890" from the RFC 6238 test vectors
+const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+fn configure(config: &mut AppConfig) {
+    config.auth_config.totp_secret = Some(SECRET.parse().unwrap());
+}
+
+fn current_code() -> String {
+    SECRET
+        .parse::<TotpSecret>()
+        .unwrap()
+        .code(SystemTime::now())
+}
+
+async fn login(sut: &Sut, password: &str, totp: Option<String>) -> StatusCode {
+    sut.request(Method::POST, "/auth/login")
+        .json(&LoginForm {
+            password: password.into(),
+            totp,
+        })
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+#[test]
+fn generates_rfc_6238_codes() {
+    let secret = SECRET.parse::<TotpSecret>().unwrap();
+
+    for (time, code) in [
+        (59, "287082"),
+        (1111111109, "081804"),
+        (1234567890, "005924"),
+        (20000000000, "353130"),
+    ] {
+        assert_eq!(secret.code(UNIX_EPOCH + Duration::from_secs(time)), code);
+    }
+}
+
+#[tokio::test]
+async fn login_page_shows_code_input_when_enabled() {
+    let html = Sut::with(configure)
+        .await
+        .request(Method::GET, "/auth/login")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    assert!(html.contains("name=\"totp\""));
+
+    let html = Sut::default()
+        .await
+        .request(Method::GET, "/auth/login")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    assert!(!html.contains("name=\"totp\""));
+}
+
+#[tokio::test]
+async fn login_returns_401_without_code() {
+    let sut = Sut::with(configure).await;
+
+    assert_eq!(login(&sut, PASSWORD, None).await, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn login_returns_401_with_incorrect_code() {
+    let sut = Sut::with(configure).await;
+    let incorrect = format!(
+        "{:06}",
+        (current_code().parse::<u32>().unwrap() + 1) % 1000000
+    );
+
+    assert_eq!(
+        login(&sut, PASSWORD, Some(incorrect)).await,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn login_returns_401_with_incorrect_password() {
+    let sut = Sut::with(configure).await;
+
+    assert_eq!(
+        login(&sut, "invalid", Some(current_code())).await,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn login_grants_session_with_correct_code() {
+    let sut = Sut::with(configure).await;
+
+    assert_eq!(
+        login(&sut, PASSWORD, Some(current_code())).await,
+        StatusCode::OK
+    );
+
+    let res = sut
+        .request(Method::GET, "/auth_request")
+        .header("X-Original-URI", ORIGINAL_URI)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let sessions = sut.app_handle.datastore().list_sessions().await.unwrap();
+    assert_eq!(sessions[0].login_method, Some(LoginMethod::PasswordTotp));
+}
+
+#[tokio::test]
+async fn login_rejects_reused_code() {
+    let sut = Sut::with(configure).await;
+    let code = current_code();
+
+    assert_eq!(
+        login(&sut, PASSWORD, Some(code.clone())).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        login(&sut, PASSWORD, Some(code)).await,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn basic_auth_does_not_require_code() {
+    let sut = Sut::with(|config| {
+        configure(config);
+        config.auth_config.allow_basic = true;
+    })
+    .await;
+
+    let res = sut
+        .request(Method::GET, "/auth_request")
+        .header("X-Original-URI", ORIGINAL_URI)
+        .basic_auth("user", Some(PASSWORD))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+}
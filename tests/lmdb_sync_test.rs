@@ -0,0 +1,18 @@
+use dumb_auth::{Datastore, DatastoreOptions, ReadMode, WriteMode};
+use tempfile::TempDir;
+
+fn create_datastore() -> (Datastore, TempDir) {
+    let dir = TempDir::new().unwrap();
+    let datastore = Datastore::open_with(
+        dir.path().join("dumb-auth.mdb"),
+        DatastoreOptions {
+            read_mode: ReadMode::Sync,
+            write_mode: WriteMode::Sync,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    (datastore, dir)
+}
+
+mod integration;
@@ -197,6 +197,7 @@ async fn session_auth() {
         .post(res.url().as_str())
         .json(&LoginForm {
             password: PASSWORD.into(),
+            totp: None,
         })
         .send()
         .await
@@ -206,8 +207,7 @@ async fn session_auth() {
     assert_eq!(res.status(), StatusCode::OK);
     assert!(res
         .cookies()
-        .find(|c| c.name() == AuthConfig::DEFAULT_SESSION_COOKIE_NAME)
-        .is_some());
+        .any(|c| c.name() == AuthConfig::DEFAULT_SESSION_COOKIE_NAME));
 
     // Make now-authenticated request
     let res = client
@@ -0,0 +1,59 @@
+use std::{fs, process::Command};
+
+use dumb_auth::{AppConfig, AuthConfig, Datastore, LoginForm, Password};
+use reqwest::{Client, StatusCode};
+use tokio::net::TcpListener;
+
+const PASSWORD: &str = "hunter2";
+
+#[tokio::test]
+async fn revoke_writes_audit_log() {
+    let dir = tempfile::tempdir().unwrap();
+    let datastore_path = dir.path().join("dumb-auth.mdb");
+    let audit_log = dir.path().join("audit.log");
+
+    let config = AppConfig::default(AuthConfig::default(Password::Plain(PASSWORD.into())));
+    let (app, handle) =
+        dumb_auth::app_with_handle(config, Datastore::open(&datastore_path).unwrap());
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async { axum::serve(listener, app).await.unwrap() });
+
+    let res = Client::new()
+        .post(format!("http://{}/auth/login", addr))
+        .json(&LoginForm {
+            password: PASSWORD.into(),
+            totp: None,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    server.abort();
+
+    let sessions = handle.datastore().list_sessions().await.unwrap();
+    assert_eq!(sessions.len(), 1);
+    let id = sessions[0].id.0.to_string();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dumb-auth"))
+        .args(["sessions", "revoke", &id])
+        .arg(format!("--datastore={}", datastore_path.display()))
+        .arg(format!("--audit-log={}", audit_log.display()))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(handle.datastore().list_sessions().await.unwrap().is_empty());
+
+    let events = fs::read_to_string(&audit_log).unwrap();
+    assert!(
+        events.lines().any(|line| {
+            line.contains("\"event\":\"session_revoked\"")
+                && line.contains(&format!("\"session_id\":{}", id))
+        }),
+        "{}",
+        events
+    );
+
+    // Only the audit log gets audit events
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("session_revoked"));
+}
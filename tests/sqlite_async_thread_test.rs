@@ -0,0 +1,18 @@
+use dumb_auth::{Datastore, DatastoreOptions, ReadMode, WriteMode};
+use tempfile::TempDir;
+
+fn create_datastore() -> (Datastore, TempDir) {
+    let dir = TempDir::new().unwrap();
+    let datastore = Datastore::open_sqlite_with(
+        dir.path().join("dumb-auth.db"),
+        DatastoreOptions {
+            read_mode: ReadMode::Async,
+            write_mode: WriteMode::AsyncThread,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    (datastore, dir)
+}
+
+mod integration;